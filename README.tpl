@@ -8,6 +8,15 @@ Copyright © 2024 Bart Massey (Version {{version}})
 
 {{readme}}
 
+# Testing
+
+The crate is built for the MB2 by default (see
+`.cargo/config.toml`), so its host tests only run when the
+host target is given explicitly, along with the features
+under test:
+
+    cargo test --lib --target x86_64-unknown-linux-gnu --all-features
+
 # License
 
 This work is licensed under the "MIT License". Please see the file
@@ -0,0 +1,36 @@
+#![no_main]
+#![no_std]
+
+use panic_rtt_target as _;
+use rtt_target::rtt_init_print;
+
+use cortex_m_rt::entry;
+use microbit::board::Board;
+
+use mb2_wukong_expansion::{ambient::ws2812::FrameBuffer, motor, WuKongAmbient, WuKongBus, RGB8};
+
+#[entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let board = Board::take().unwrap();
+    let i2c = board.i2c_external;
+    let mut wkb = WuKongBus::new(board.TWIM0, i2c.scl, i2c.sda);
+    let m1 = motor!(1);
+    let mut wka = WuKongAmbient::new(board.PWM0, board.edge.e16).unwrap();
+    wka.set_deferred(true).unwrap();
+
+    let mut frame = Some(cortex_m::singleton!(: FrameBuffer<4> = FrameBuffer::new()).unwrap());
+    let mut step = 0u8;
+    loop {
+        // Start the next LED frame as soon as the last one is done.
+        if let Some(buffer) = wka.poll_show().or(frame.take()) {
+            wka.set_all(RGB8::new(step, 0, 64 - step / 4)).unwrap();
+            wka.start_show(buffer).map_err(|(e, _)| e).unwrap();
+            step = step.wrapping_add(1);
+        }
+        // Meanwhile, keep talking to the motor.
+        let speed = step as i8 / 2;
+        wkb.set_motor_velocity(m1, speed).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+#![no_main]
+#![no_std]
+
+use panic_rtt_target as _;
+use rtt_target::rtt_init_print;
+
+use cortex_m_rt::entry;
+use microbit::{board::Board, hal::Timer};
+
+use mb2_wukong_expansion::{
+    ambient::animation::{Animation, Animator},
+    WuKongAmbient, RGB8,
+};
+
+#[entry]
+fn main() -> ! {
+    rtt_init_print!();
+
+    let board = Board::take().unwrap();
+    // Free-running microsecond clock, which wraps about every
+    // 71.6 minutes.
+    let mut clock = Timer::periodic(board.TIMER0);
+    clock.start(u32::MAX);
+    // Millisecond clock accumulated from it, which wraps at
+    // 2^32 ms as the Animator expects. It must be read at
+    // least once per microsecond clock wrap.
+    let mut last_us = clock.read();
+    let mut us = 0;
+    let mut ms = 0u32;
+    let mut now = move || {
+        let read = clock.read();
+        us += read.wrapping_sub(last_us);
+        last_us = read;
+        ms = ms.wrapping_add(us / 1000);
+        us %= 1000;
+        ms
+    };
+
+    let mut wka = WuKongAmbient::new(board.PWM0, board.edge.e16).unwrap();
+    let animations = [
+        Animation::Rainbow {
+            period: 2000,
+            brightness: 64,
+        },
+        Animation::Comet {
+            color: RGB8::new(0, 64, 64),
+            period: 1000,
+        },
+        Animation::Police { period: 800 },
+    ];
+    let mut animator = Animator::new(animations[0], now()).unwrap();
+    let mut next = 1;
+    let mut switched = now();
+    loop {
+        let t = now();
+        if t.wrapping_sub(switched) >= 5000 {
+            animator.set_animation(animations[next], t).unwrap();
+            next = (next + 1) % animations.len();
+            switched = t;
+        }
+        animator.update(&mut wka, t).unwrap();
+    }
+}
@@ -21,7 +21,7 @@ fn main() -> ! {
 
     loop {
         wukong
-            .set_mood_lights(&mut delay, MoodLights::Breath)
+            .set_mood_lights(&mut delay, MoodLights::BREATH)
             .unwrap();
         delay.delay_ms(4000);
         for intensity in (0..=100).step_by(10) {
@@ -8,7 +8,7 @@ use cortex_m_rt::entry;
 use embedded_hal::delay::DelayNs;
 use microbit::{board::Board, hal::Timer};
 
-use mb2_wukong_expansion::{Motor, WuKongBus};
+use mb2_wukong_expansion::{motor, WuKongBus};
 
 #[entry]
 fn main() -> ! {
@@ -18,7 +18,7 @@ fn main() -> ! {
     let mut timer = Timer::new(board.TIMER0);
     let i2c = board.i2c_external;
     let mut wkb = WuKongBus::new(board.TWIM0, i2c.scl, i2c.sda);
-    let m1 = Motor::new(1).unwrap();
+    let m1 = motor!(1);
 
     loop {
         for i in [-100, 0, 100] {
@@ -8,7 +8,19 @@ use cortex_m_rt::entry;
 use embedded_hal::delay::DelayNs;
 use microbit::{board::Board, hal::Timer};
 
-use mb2_wukong_expansion::{Servo, ServoAngle, ServoConfig, WuKongBus};
+use mb2_wukong_expansion::{
+    configured_servo, servo_angle, servo_config, ConfiguredServo, ServoAngle, ServoConfig,
+    ServoLayout, WuKongBus,
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Layout;
+
+impl ServoLayout for Layout {
+    const CONFIG: ServoConfig = servo_config![1 => 180];
+}
+
+const S1: ConfiguredServo<Layout> = configured_servo!(Layout, 1);
 
 #[entry]
 fn main() -> ! {
@@ -17,20 +29,16 @@ fn main() -> ! {
     let board = Board::take().unwrap();
     let mut timer = Timer::new(board.TIMER0);
     let i2c = board.i2c_external;
-    let mut wkb = WuKongBus::new(board.TWIM0, i2c.scl, i2c.sda);
-    let s1 = Servo::new(1).unwrap();
-    let servo_config = ServoConfig::new([
-        (s1, ServoAngle::new(180).unwrap()),
-    ]).unwrap();
+    let mut wkb = WuKongBus::new(board.TWIM0, i2c.scl, i2c.sda).with_servo_layout::<Layout>();
 
-    let a0 = ServoAngle::new(0).unwrap();
+    let a0 = servo_angle!(0);
     loop {
         for i in 0..=180 {
             let a = ServoAngle::new(i).unwrap();
-            wkb.set_servo_angle(&servo_config, s1, a).unwrap();
+            wkb.set_servo_angle(S1, a).unwrap();
             timer.delay_ms(30);
         }
-        wkb.set_servo_angle(&servo_config, s1, a0).unwrap();
+        wkb.set_servo_angle(S1, a0).unwrap();
         timer.delay_ms(1000);
     }
 }
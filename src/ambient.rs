@@ -4,81 +4,632 @@ Wukong board. These are WS2812-family "smart" LEDs — they
 appear to be WS2812B.
 
 Driving these parts is difficult due to tight timing constraints.
-We defer this to the `ws2812-nrf52833-pwm` crate, which uses
-a Microbit PWM to generate the necessary signals.
+The [ws2812] module uses either a Microbit PWM or SPIM unit
+to generate the necessary signals; the driver uses whichever
+kind of unit it is given.
+
+The number of LEDs is a const generic parameter, 4 by
+default. A driver for the onboard LEDs is made with
+[WuKongAmbient::new]; [WuKongAmbient::with_pin] drives a
+chain of any length on another pin, such as a NeoPixel strip
+attached to the Wukong edge-connector breakout.
+
+Normally every change is sent to the LEDs at once. In
+deferred mode, changes are staged and sent together by
+[WuKongAmbient::show], so that a multi-LED animation frame
+takes a single transfer and shows no tearing.
+
+LEDs can be addressed either by their index in the WS2812
+chain or by [Corner]. Corners are relative to the robot
+chassis: an [Orientation] can be set to describe how the
+Wukong is mounted on it. The order in which the onboard
+LEDs sit along the chain has not been checked against the
+Wukong schematic or on hardware: see
+[WuKongAmbient::set_chain_order].
+
+The [color] module has HSV and HSL colors and other color
+helpers, the [animation] module has non-blocking animated
+effects, the [crossfade] module has smooth non-blocking
+crossfades, and the [gauge] module shows values on the
+corner LEDs.
+
+[WuKongAmbient] implements
+[SmartLedsWrite], so it
+can be used with `smart-leds` adaptors such as
+`brightness()` and `gamma()`, and with other code written
+for that trait.
+
+Each change normally waits for the transfer to the LEDs to
+finish. With a PWM unit, [WuKongAmbient::start_show] instead
+sends the colors by DMA from a static
+[FrameBuffer](ws2812::FrameBuffer) and returns at once, so
+that the transfer overlaps with other work such as I2C
+motor commands; [WuKongAmbient::poll_show] gives the buffer
+back when it is done. Other changes fail with
+[ws2812::Error::Busy] until then, so deferred mode is
+useful here.
+
+Colors are linear duty values. A brightness [Correction]
+can be set to be applied as colors are sent to the LEDs, so
+that linear fades look smooth.
+
+Four WS2812s at full white draw a lot of current from the
+Wukong battery. A global brightness scale and a current
+limit can be set: with a limit, each frame is dimmed as it
+is sent so that its estimated draw stays within the limit.
+The estimate is rough — about
+[WuKongAmbient::CHANNEL_CURRENT] per fully-on color channel
+plus [WuKongAmbient::IDLE_CURRENT] per LED — so leave some
+margin.
 */
 
+pub mod animation;
+pub mod color;
+pub mod crossfade;
+pub mod gauge;
+pub mod ws2812;
+
 pub use smart_leds::RGB8;
 
-use nrf52833_hal::{gpio, pwm};
+use crate::correction::Correction;
+
+use libm::roundf;
+use nrf52833_hal::gpio;
 use smart_leds_trait::SmartLedsWrite;
-use ws2812_nrf52833_pwm::{self as ws2812, Ws2812};
 
-/// Ambient LED driver struct.
-pub struct WuKongAmbient<PWM>
+/// Ambient LED driver struct, for a chain of `N` LEDs.
+pub struct WuKongAmbient<P, const N: usize = 4>
 where
-    PWM: pwm::Instance,
+    P: ws2812::Instance,
 {
-    ambient: Ws2812<{ 4 * 24 }, PWM>,
-    rgb_colors: [RGB8; 4],
+    ambient: P::Driver<N>,
+    rgb_colors: [RGB8; N],
+    correction: Correction,
+    deferred: bool,
+    orientation: Orientation,
+    chain: [usize; 4],
+    brightness: u8,
+    current_limit: Option<u32>,
 }
 
-impl<PWM> core::fmt::Debug for WuKongAmbient<PWM>
+impl<P, const N: usize> core::fmt::Debug for WuKongAmbient<P, N>
 where
-    PWM: pwm::Instance,
+    P: ws2812::Instance,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "rgb_colors: {:?}", self.rgb_colors)
     }
 }
 
+/// Corner of the chassis. With the Wukong mounted in its
+/// default [Orientation], the front of the chassis is the
+/// direction faced by the LED display of the MB2 standing in
+/// the Wukong's edge-connector slot. Left and right are as
+/// seen from behind, looking forward: the MB2's button A,
+/// which is on the left when looking at the display, is on
+/// the right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    /// Front left corner.
+    FrontLeft,
+    /// Front right corner.
+    FrontRight,
+    /// Back right corner.
+    BackRight,
+    /// Back left corner.
+    BackLeft,
+}
+
+impl Corner {
+    /// All corners, clockwise from front left seen from
+    /// above.
+    pub const ALL: [Corner; 4] = [
+        Corner::FrontLeft,
+        Corner::FrontRight,
+        Corner::BackRight,
+        Corner::BackLeft,
+    ];
+
+    /// Position clockwise from front left.
+    fn ring(self) -> usize {
+        self as usize
+    }
+}
+
+/// Rotation of the Wukong on the chassis, clockwise seen
+/// from above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// Not rotated.
+    R0,
+    /// Rotated a quarter turn clockwise.
+    R90,
+    /// Rotated a half turn.
+    R180,
+    /// Rotated three quarter turns clockwise.
+    R270,
+}
+
+/// Mounting of the Wukong on the chassis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation {
+    /// Rotation of the board.
+    pub rotation: Rotation,
+    /// If true, swap left and right (in the chassis frame,
+    /// before rotation is applied).
+    pub mirrored: bool,
+}
+
+impl Orientation {
+    /// The board is mounted with its front to the front of
+    /// the chassis.
+    pub const DEFAULT: Orientation = Orientation {
+        rotation: Rotation::R0,
+        mirrored: false,
+    };
+
+    /// The board corner at the chassis `corner`: that is,
+    /// the corner that would be at `corner` with the board
+    /// in the default orientation.
+    pub fn board_corner(self, corner: Corner) -> Corner {
+        let mut ring = corner.ring();
+        if self.mirrored {
+            ring = (5 - ring) % 4;
+        }
+        Corner::ALL[(ring + 4 - self.rotation as usize) % 4]
+    }
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 /// Error during ambient driver operation.
-pub enum Error<PWM> {
+#[allow(clippy::enum_variant_names)]
+pub enum Error<P> {
     /// WS2812 error.
-    Ws2812Error(ws2812::Error<PWM>),
+    Ws2812Error(ws2812::Error<P>),
     /// Bad index.
     IndexError(usize),
+    /// Given corner repeated in a chain order.
+    ChainError(Corner),
+    /// Wrong number of colors written: the number given, or
+    /// one more than the number of LEDs if there were too
+    /// many.
+    LengthError(usize),
 }
 
-impl<PWM> core::fmt::Debug for Error<PWM> {
+impl<P> core::fmt::Debug for Error<P> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Error::Ws2812Error(err) => write!(f, "WS2812 error: {:?}", err),
             Error::IndexError(index) => write!(f, "index error: {}", index),
+            Error::ChainError(corner) => write!(f, "chain error: {:?}", corner),
+            Error::LengthError(len) => write!(f, "length error: {}", len),
         }
     }
 }
 
-impl<PWM> WuKongAmbient<PWM>
+impl<P> WuKongAmbient<P>
 where
-    PWM: pwm::Instance,
+    P: ws2812::Instance,
 {
-    /// Make a new ambient driver. This takes ownership of
-    /// the specific pin attached to the WS2812 chain (MB2
-    /// P16), and thus can only be instantiated once.  It
-    /// also requires a PWM unit to drive the chain
+    /// Make a new ambient driver for the onboard LEDs. This
+    /// takes ownership of the specific pin attached to the
+    /// WS2812 chain (MB2 P16), and thus can only be
+    /// instantiated once.  It also requires a PWM or SPIM
+    /// unit to drive the chain with. The LEDs are all
+    /// initialized to off.
+    pub fn new<PinMode>(instance: P, pin: gpio::p1::P1_02<PinMode>) -> Result<Self, Error<P>> {
+        Self::with_pin(instance, pin.degrade())
+    }
+
+    /// How the Wukong is mounted on the chassis.
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// Set how the Wukong is mounted on the chassis. This
+    /// changes how [Corner]s are mapped to LEDs, but not the
+    /// current LED colors.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+    }
+
+    /// The board corners of the LEDs, in chain order.
+    pub fn chain_order(&self) -> [Corner; 4] {
+        let mut corners = Corner::ALL;
+        for corner in Corner::ALL {
+            corners[self.chain[corner.ring()]] = corner;
+        }
+        corners
+    }
+
+    /// Set the board corners of the LEDs, in chain order.
+    /// This changes how [Corner]s are mapped to LEDs, but not
+    /// the current LED colors.
+    ///
+    /// The default order is [Corner::ALL]: the chain is
+    /// taken to run clockwise from front left seen from
+    /// above. This is an assumption that has not been
+    /// checked against the Wukong schematic or on hardware.
+    /// To check it, light the LEDs one at a time with
+    /// [Self::set_color] and note where each one is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a corner is repeated in `corners`.
+    pub fn set_chain_order(&mut self, corners: [Corner; 4]) -> Result<(), Error<P>> {
+        let mut chain = [0; 4];
+        let mut seen = 0u8;
+        for (index, corner) in corners.into_iter().enumerate() {
+            let ring = corner.ring();
+            if seen & (1 << ring) != 0 {
+                return Err(Error::ChainError(corner));
+            }
+            seen |= 1 << ring;
+            chain[ring] = index;
+        }
+        self.chain = chain;
+        Ok(())
+    }
+
+    /// Chain index of the LED at the chassis `corner`.
+    pub fn corner_index(&self, corner: Corner) -> usize {
+        self.chain[self.orientation.board_corner(corner).ring()]
+    }
+
+    /// The color of the LED at the chassis `corner`.
+    pub fn corner_color(&self, corner: Corner) -> RGB8 {
+        self.rgb_colors[self.corner_index(corner)]
+    }
+
+    /// Set the LED at the chassis `corner` to the given
+    /// `color`.
+    pub fn set_corner_color(&mut self, corner: Corner, color: RGB8) -> Result<(), Error<P>> {
+        self.rgb_colors[self.corner_index(corner)] = color;
+        self.update()
+    }
+
+    /// Set the LEDs at each chassis corner to the given
+    /// `colors`, given in [Corner::ALL] order, in a single
+    /// transfer.
+    pub fn set_corner_colors(&mut self, colors: [RGB8; 4]) -> Result<(), Error<P>> {
+        for (corner, color) in Corner::ALL.into_iter().zip(colors) {
+            let index = self.corner_index(corner);
+            self.rgb_colors[index] = color;
+        }
+        self.update()
+    }
+}
+
+impl<P, const N: usize> WuKongAmbient<P, N>
+where
+    P: ws2812::Instance,
+{
+    /// Make a new driver for a chain of `N` WS2812-family
+    /// LEDs on the given `pin`, for example an external
+    /// strip on a Wukong edge-connector breakout pin. It
+    /// requires a PWM or SPIM unit to drive the chain
     /// with. The LEDs are all initialized to off.
-    pub fn new<PinMode>(pwm: PWM, pin: gpio::p1::P1_02<PinMode>) -> Result<Self, Error<PWM>> {
-        let ambient: Ws2812<{ 4 * 24 }, _> = Ws2812::new(pwm, pin.degrade());
-        let rgb_colors = [RGB8::default(); 4];
+    pub fn with_pin<PinMode>(instance: P, pin: gpio::Pin<PinMode>) -> Result<Self, Error<P>> {
+        let ambient = instance.driver(pin);
+        let rgb_colors = [RGB8::default(); N];
         let mut ambient = Self {
             ambient,
             rgb_colors,
+            correction: Correction::Linear,
+            deferred: false,
+            orientation: Orientation::DEFAULT,
+            chain: [0, 1, 2, 3],
+            brightness: 255,
+            current_limit: None,
         };
         ambient.send_colors()?;
         Ok(ambient)
     }
 
-    fn send_colors(&mut self) -> Result<(), Error<PWM>> {
-        self.ambient.write(self.rgb_colors).map_err(|e| Error::Ws2812Error(e))
+    /// Estimated current in milliamps drawn by one LED
+    /// color channel at full duty.
+    pub const CHANNEL_CURRENT: u32 = 20;
+
+    /// Estimated current in milliamps drawn by one LED when
+    /// dark.
+    pub const IDLE_CURRENT: u32 = 1;
+
+    /// The colors as they will be sent: corrected, scaled
+    /// by the brightness, and dimmed to the current limit.
+    fn output_colors(&self) -> [RGB8; N] {
+        let correction = self.correction;
+        let brightness = self.brightness as u32;
+        let mut colors = self.rgb_colors.map(|color| {
+            let color = correction.apply_rgb(color);
+            scale(color, brightness, 255)
+        });
+        if let Some(limit) = self.current_limit {
+            // Compare in units of 1/255 mA to avoid rounding.
+            let idle = Self::IDLE_CURRENT * colors.len() as u32;
+            let budget = limit.saturating_sub(idle).saturating_mul(255);
+            let draw = channel_sum(&colors) * Self::CHANNEL_CURRENT;
+            if draw > budget {
+                colors = colors.map(|color| scale(color, budget, draw));
+            }
+        }
+        colors
     }
 
-    /// Set a specific LED by `index` (0..=3) to a specific `color`.
-    pub fn set_color(&mut self, index: usize, color: RGB8) -> Result<(), Error<PWM>> {
+    fn send_colors(&mut self) -> Result<(), Error<P>> {
+        let colors = self.output_colors();
+        P::write(&mut self.ambient, &colors).map_err(|e| Error::Ws2812Error(e))
+    }
+
+    /// Send high-precision `colors`, with channels in
+    /// 0.0..=255.0, dithered to 8 bits. `error` carries the
+    /// rounding error of each channel from frame to frame,
+    /// so that over several frames the LEDs average to the
+    /// precise colors. The correction, brightness and
+    /// current limit are applied before dithering. The
+    /// colors are set to `colors` rounded; in deferred mode
+    /// nothing is sent.
+    pub(crate) fn send_dithered(
+        &mut self,
+        colors: &[[f32; 3]; N],
+        error: &mut [[f32; 3]; N],
+    ) -> Result<(), Error<P>> {
+        let to_u8 = |x: f32| roundf(x.clamp(0.0, 255.0)) as u8;
+        self.rgb_colors = colors.map(|[r, g, b]| RGB8::new(to_u8(r), to_u8(g), to_u8(b)));
+        if self.deferred {
+            return Ok(());
+        }
+        let correction = self.correction;
+        let brightness = self.brightness as f32;
+        let mut duty = colors.map(|c| c.map(|x| brightness * correction.apply_f32(x / 255.0)));
+        if let Some(limit) = self.current_limit {
+            let idle = Self::IDLE_CURRENT * N as u32;
+            let budget = limit.saturating_sub(idle) as f32;
+            let draw = duty.iter().flatten().sum::<f32>() * Self::CHANNEL_CURRENT as f32 / 255.0;
+            if draw > budget {
+                let f = budget / draw;
+                duty = duty.map(|c| c.map(|x| x * f));
+            }
+        }
+        let mut out = [RGB8::default(); N];
+        for ((color, duty), error) in out.iter_mut().zip(duty).zip(error.iter_mut()) {
+            let [r, g, b]: [u8; 3] = core::array::from_fn(|i| {
+                let x = duty[i] + error[i];
+                let q = to_u8(x);
+                error[i] = x - q as f32;
+                q
+            });
+            *color = RGB8::new(r, g, b);
+        }
+        P::write(&mut self.ambient, &out).map_err(|e| Error::Ws2812Error(e))
+    }
+
+    /// Send the colors unless in deferred mode.
+    fn update(&mut self) -> Result<(), Error<P>> {
+        if self.deferred {
+            return Ok(());
+        }
+        self.send_colors()
+    }
+
+    /// True if in deferred mode.
+    pub fn is_deferred(&self) -> bool {
+        self.deferred
+    }
+
+    /// Turn deferred mode on or off. In deferred mode,
+    /// changes are only sent to the LEDs by
+    /// [WuKongAmbient::show]. Turning deferred mode off sends
+    /// any staged changes.
+    pub fn set_deferred(&mut self, deferred: bool) -> Result<(), Error<P>> {
+        self.deferred = deferred;
+        self.update()
+    }
+
+    /// Send the current colors to the LEDs, whether or not
+    /// in deferred mode.
+    pub fn show(&mut self) -> Result<(), Error<P>> {
+        self.send_colors()
+    }
+
+    /// The brightness correction applied to colors.
+    pub fn correction(&self) -> Correction {
+        self.correction
+    }
+
+    /// Set the brightness `correction` applied to colors.
+    /// Unless in deferred mode, the current colors are
+    /// resent with it.
+    pub fn set_correction(&mut self, correction: Correction) -> Result<(), Error<P>> {
+        self.correction = correction;
+        self.update()
+    }
+
+    /// The global brightness scale, 0..=255.
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Set the global `brightness` scale: colors are
+    /// multiplied by `brightness`/255 as they are sent.
+    /// Unless in deferred mode, the current colors are
+    /// resent with it.
+    pub fn set_brightness(&mut self, brightness: u8) -> Result<(), Error<P>> {
+        self.brightness = brightness;
+        self.update()
+    }
+
+    /// The current limit in milliamps, if any.
+    pub fn current_limit(&self) -> Option<u32> {
+        self.current_limit
+    }
+
+    /// Set the current limit in milliamps, or `None` for no
+    /// limit. Frames whose estimated draw is over the limit
+    /// are dimmed evenly to fit. A limit below the idle draw
+    /// of the LEDs turns them all off. Unless in deferred
+    /// mode, the current colors are resent with it.
+    pub fn set_current_limit(&mut self, current_limit: Option<u32>) -> Result<(), Error<P>> {
+        self.current_limit = current_limit;
+        self.update()
+    }
+
+    /// Estimated current in milliamps drawn by the LEDs
+    /// showing the current colors, after brightness scaling
+    /// and the current limit are applied. In deferred mode
+    /// this includes staged changes not yet shown.
+    pub fn estimated_current(&self) -> u32 {
+        let colors = self.output_colors();
+        let idle = Self::IDLE_CURRENT * colors.len() as u32;
+        idle + (channel_sum(&colors) * Self::CHANNEL_CURRENT).div_ceil(255)
+    }
+
+    /// The color of the LED at `index` (0..`N`), or `None`
+    /// if there is no such LED. In deferred mode this
+    /// includes staged changes not yet shown.
+    pub fn color(&self, index: usize) -> Option<RGB8> {
+        self.rgb_colors.get(index).copied()
+    }
+
+    /// The colors of all the LEDs, in index order. In
+    /// deferred mode this includes staged changes not yet
+    /// shown.
+    pub fn colors(&self) -> &[RGB8; N] {
+        &self.rgb_colors
+    }
+
+    /// Iterate over the colors of the LEDs, in index order.
+    pub fn iter(&self) -> core::slice::Iter<'_, RGB8> {
+        self.rgb_colors.iter()
+    }
+
+    /// Set a specific LED by `index` (0..`N`) to a specific `color`.
+    pub fn set_color(&mut self, index: usize, color: RGB8) -> Result<(), Error<P>> {
         if index >= self.rgb_colors.len() {
             return Err(Error::IndexError(index));
         }
         self.rgb_colors[index] = color;
-        self.send_colors()
+        self.update()
+    }
+
+    /// Set all the LEDs to the same `color`, in a single
+    /// transfer.
+    pub fn set_all(&mut self, color: RGB8) -> Result<(), Error<P>> {
+        self.set_colors([color; N])
+    }
+
+    /// Set the LEDs to the given `colors`, in index order,
+    /// in a single transfer.
+    pub fn set_colors(&mut self, colors: [RGB8; N]) -> Result<(), Error<P>> {
+        self.rgb_colors = colors;
+        self.update()
+    }
+}
+
+/// Scale each channel of `color` by `num`/`den`, rounding
+/// down.
+fn scale(color: RGB8, num: u32, den: u32) -> RGB8 {
+    let channel = |c: u8| (c as u32 * num / den) as u8;
+    RGB8::new(channel(color.r), channel(color.g), channel(color.b))
+}
+
+/// Sum of all the channels of `colors`.
+fn channel_sum(colors: &[RGB8]) -> u32 {
+    colors
+        .iter()
+        .map(|c| c.r as u32 + c.g as u32 + c.b as u32)
+        .sum()
+}
+
+impl<P, const N: usize> WuKongAmbient<P, N>
+where
+    P: ws2812::NonBlocking,
+{
+    /// Start sending the current colors to the LEDs using
+    /// `frame`, whether or not in deferred mode, and return
+    /// without waiting for the transfer. Call
+    /// [WuKongAmbient::poll_show] to find out when it is
+    /// done and get `frame` back. Until then, anything else
+    /// that sends to the LEDs fails with
+    /// [ws2812::Error::Busy].
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if a transfer is in progress.
+    /// * Returns an error if the transfer cannot be started.
+    ///
+    /// `frame` is handed back with the error.
+    pub fn start_show(
+        &mut self,
+        frame: &'static mut ws2812::FrameBuffer<N>,
+    ) -> Result<(), (Error<P>, &'static mut ws2812::FrameBuffer<N>)> {
+        let colors = self.output_colors();
+        P::start_write(&mut self.ambient, &colors, frame)
+            .map_err(|(e, frame)| (Error::Ws2812Error(e), frame))
+    }
+
+    /// True if a transfer started by
+    /// [WuKongAmbient::start_show] has not yet been
+    /// collected by [WuKongAmbient::poll_show].
+    pub fn is_showing(&self) -> bool {
+        P::is_busy(&self.ambient)
+    }
+
+    /// If a transfer started by [WuKongAmbient::start_show]
+    /// has finished, return its frame buffer; otherwise
+    /// return `None`. This does not block.
+    pub fn poll_show(&mut self) -> Option<&'static mut ws2812::FrameBuffer<N>> {
+        P::poll(&mut self.ambient)
+    }
+}
+
+impl<P, const N: usize> SmartLedsWrite for WuKongAmbient<P, N>
+where
+    P: ws2812::Instance,
+{
+    type Error = Error<P>;
+    type Color = RGB8;
+
+    /// Set the LEDs to the colors from `iterator`, in index
+    /// order, in a single transfer. This behaves like
+    /// [WuKongAmbient::set_colors]: the correction,
+    /// brightness and current limit are applied, and in
+    /// deferred mode the colors are only staged.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error, leaving the colors unchanged, if
+    ///   `iterator` does not give exactly `N` colors.
+    /// * Returns an error if the LED transfer fails.
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        let mut colors = [RGB8::default(); N];
+        let mut iterator = iterator.into_iter();
+        for (len, color) in colors.iter_mut().enumerate() {
+            *color = iterator.next().ok_or(Error::LengthError(len))?.into();
+        }
+        if iterator.next().is_some() {
+            return Err(Error::LengthError(N + 1));
+        }
+        self.set_colors(colors)
+    }
+}
+
+impl<'a, P, const N: usize> IntoIterator for &'a WuKongAmbient<P, N>
+where
+    P: ws2812::Instance,
+{
+    type Item = &'a RGB8;
+    type IntoIter = core::slice::Iter<'a, RGB8>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
@@ -0,0 +1,232 @@
+/*!
+Non-blocking animated effects for the ambient LEDs. An
+[Animator] runs an [Animation]: call [Animator::update]
+regularly with the current time in milliseconds, and it will
+send a new frame to the LEDs in a single transfer whenever
+the frame interval has passed.
+
+Effects that move around the board do so clockwise by
+[Corner], so they follow the board
+[Orientation](super::Orientation).
+*/
+
+use super::{color, ws2812, Corner, Error as AmbientError, WuKongAmbient, RGB8};
+
+use libm::cosf;
+/// Error in animation setup.
+#[derive(Debug, Clone, Copy)]
+pub enum Error {
+    /// Animation period must be positive.
+    InvalidPeriod(u32),
+}
+
+/// An animated effect. Periods are in milliseconds.
+#[derive(Debug, Clone, Copy)]
+pub enum Animation {
+    /// Hues cycle around the color wheel once per `period`,
+    /// spread evenly around the corners, at the given
+    /// `brightness`.
+    Rainbow { period: u32, brightness: u8 },
+    /// A single lit LED of `color` steps around the corners
+    /// once per `period`.
+    Chase { color: RGB8, period: u32 },
+    /// All LEDs fade smoothly up to `color` and back down
+    /// once per `period`.
+    Breathe { color: RGB8, period: u32 },
+    /// All LEDs are `color` for the first half of each
+    /// `period` and off for the second.
+    Blink { color: RGB8, period: u32 },
+    /// Left LEDs double-flash red, then right LEDs
+    /// double-flash blue, once per `period`.
+    Police { period: u32 },
+    /// A `color` comet with a fading tail circles the
+    /// corners once per `period`.
+    Comet { color: RGB8, period: u32 },
+    /// A pseudo-random LED flashes `color` once per
+    /// `period`.
+    Sparkle { color: RGB8, period: u32 },
+}
+
+/// Scale `color` by `f` (0.0..=1.0).
+fn scale(color: RGB8, f: f32) -> RGB8 {
+    let f = f.clamp(0.0, 1.0);
+    let channel = |c: u8| (c as f32 * f + 0.5) as u8;
+    RGB8::new(channel(color.r), channel(color.g), channel(color.b))
+}
+
+/// Cheap integer hash, for repeatable pseudo-randomness.
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^ (x >> 16)
+}
+
+impl Animation {
+    fn period(&self) -> u32 {
+        match *self {
+            Animation::Rainbow { period, .. }
+            | Animation::Chase { period, .. }
+            | Animation::Breathe { period, .. }
+            | Animation::Blink { period, .. }
+            | Animation::Police { period }
+            | Animation::Comet { period, .. }
+            | Animation::Sparkle { period, .. } => period,
+        }
+    }
+
+    /// Check that the animation period is positive.
+    fn validate(&self) -> Result<(), Error> {
+        match self.period() {
+            0 => Err(Error::InvalidPeriod(0)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Colors `elapsed` milliseconds into the animation, in
+    /// [Corner::ALL] order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the animation period is zero.
+    pub fn frame(&self, elapsed: u32) -> Result<[RGB8; 4], Error> {
+        self.validate()?;
+        Ok(self.colors(elapsed))
+    }
+
+    /// Colors `elapsed` milliseconds into an animation
+    /// already checked by [Self::validate].
+    fn colors(&self, elapsed: u32) -> [RGB8; 4] {
+        let period = self.period();
+        // Fraction of the way through the current period.
+        let t = (elapsed % period) as f32 / period as f32;
+        let off = RGB8::default();
+        match *self {
+            Animation::Rainbow { brightness, .. } => core::array::from_fn(|i| {
+                let hue = 360.0 * t + 90.0 * i as f32;
+                color::Hsv::new(hue, 1.0, brightness as f32 / 255.0).into()
+            }),
+            Animation::Chase { color, .. } => {
+                let lit = (t * 4.0) as usize;
+                core::array::from_fn(|i| if i == lit { color } else { off })
+            }
+            Animation::Breathe { color, .. } => {
+                let f = (1.0 - cosf(2.0 * core::f32::consts::PI * t)) / 2.0;
+                [scale(color, f); 4]
+            }
+            Animation::Blink { color, .. } => [if t < 0.5 { color } else { off }; 4],
+            Animation::Police { .. } => {
+                // Flash in the first and third eighths of each half.
+                let eighth = (t * 8.0) as u32;
+                let flash = eighth.is_multiple_of(2);
+                let left = eighth < 4;
+                core::array::from_fn(|i| {
+                    let is_left = matches!(Corner::ALL[i], Corner::FrontLeft | Corner::BackLeft);
+                    match (flash, left, is_left) {
+                        (true, true, true) => color::palette::RED,
+                        (true, false, false) => color::palette::BLUE,
+                        _ => off,
+                    }
+                })
+            }
+            Animation::Comet { color, .. } => {
+                let head = t * 4.0;
+                core::array::from_fn(|i| {
+                    // Distance behind the head, clockwise.
+                    let behind = (head - i as f32 + 4.0) % 4.0;
+                    let f = (1.0 - behind / 3.0).max(0.0);
+                    scale(color, f * f)
+                })
+            }
+            Animation::Sparkle { color, .. } => {
+                let lit = hash(elapsed / period) as usize % 4;
+                // Each flash fades out over its period.
+                let f = 1.0 - t;
+                core::array::from_fn(|i| if i == lit { scale(color, f) } else { off })
+            }
+        }
+    }
+}
+
+/// Non-blocking runner for an [Animation].
+#[derive(Debug, Clone)]
+pub struct Animator {
+    animation: Animation,
+    start: u32,
+    frame_interval: u32,
+    last_frame: Option<u32>,
+}
+
+impl Animator {
+    /// Default time between frames in milliseconds.
+    pub const FRAME_INTERVAL: u32 = 20;
+
+    /// Start running `animation` at time `now`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the animation period is zero.
+    pub fn new(animation: Animation, now: u32) -> Result<Self, Error> {
+        animation.validate()?;
+        Ok(Self {
+            animation,
+            start: now,
+            frame_interval: Self::FRAME_INTERVAL,
+            last_frame: None,
+        })
+    }
+
+    /// The animation being run.
+    pub fn animation(&self) -> &Animation {
+        &self.animation
+    }
+
+    /// Switch to running `animation`, starting at time
+    /// `now`. The next update will send a frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the animation period is zero.
+    pub fn set_animation(&mut self, animation: Animation, now: u32) -> Result<(), Error> {
+        *self = Self {
+            frame_interval: self.frame_interval,
+            ..Self::new(animation, now)?
+        };
+        Ok(())
+    }
+
+    /// Set the minimum time between frames in
+    /// milliseconds.
+    pub fn set_frame_interval(&mut self, frame_interval: u32) {
+        self.frame_interval = frame_interval;
+    }
+
+    /// Send the animation frame for time `now` to `ambient`
+    /// if the frame interval has passed since the last one.
+    /// Call this regularly; apart from the LED transfer
+    /// itself it does not block. If `ambient` is in deferred
+    /// mode, frames are only staged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LED transfer fails.
+    pub fn update<P>(
+        &mut self,
+        ambient: &mut WuKongAmbient<P>,
+        now: u32,
+    ) -> Result<(), AmbientError<P>>
+    where
+        P: ws2812::Instance,
+    {
+        if let Some(last) = self.last_frame {
+            if now.wrapping_sub(last) < self.frame_interval {
+                return Ok(());
+            }
+        }
+        let frame = self.animation.colors(now.wrapping_sub(self.start));
+        ambient.set_corner_colors(frame)?;
+        self.last_frame = Some(now);
+        Ok(())
+    }
+}
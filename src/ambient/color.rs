@@ -0,0 +1,270 @@
+/*!
+Color helpers for the ambient LEDs. [Hsv] and [Hsl] colors
+convert to and from [RGB8], so effects can be written in
+terms of hue and brightness. There are also helpers for
+picking colors off the hue wheel, for interpolating between
+colors, and a few named palettes.
+
+Hues are in degrees, and are wrapped into 0.0..360.0.
+Saturation, value and lightness are in 0.0..=1.0, and are
+clamped to that range.
+*/
+
+use super::RGB8;
+
+use libm::{fabsf, fmodf, roundf};
+
+/// Wrap `hue` into 0.0..360.0.
+fn wrap_hue(hue: f32) -> f32 {
+    let hue = fmodf(hue, 360.0);
+    if hue < 0.0 {
+        hue + 360.0
+    } else {
+        hue
+    }
+}
+
+/// Convert a channel in 0.0..=1.0 to a byte.
+fn to_byte(x: f32) -> u8 {
+    roundf(x.clamp(0.0, 1.0) * 255.0) as u8
+}
+
+/// Build an RGB color from hue, chroma and the amount `m`
+/// to add to each channel.
+fn from_hue_chroma(hue: f32, chroma: f32, m: f32) -> RGB8 {
+    let h = wrap_hue(hue) / 60.0;
+    let x = chroma * (1.0 - fabsf(fmodf(h, 2.0) - 1.0));
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    RGB8::new(to_byte(r + m), to_byte(g + m), to_byte(b + m))
+}
+
+/// Hue, maximum channel, minimum channel of `color`, with
+/// channels in 0.0..=1.0.
+fn hue_max_min(color: RGB8) -> (f32, f32, f32) {
+    let (r, g, b) = (
+        color.r as f32 / 255.0,
+        color.g as f32 / 255.0,
+        color.b as f32 / 255.0,
+    );
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (wrap_hue(hue), max, min)
+}
+
+/// Color as hue, saturation and value.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Hsv {
+    /// Hue in degrees.
+    pub hue: f32,
+    /// Saturation.
+    pub saturation: f32,
+    /// Value (brightness).
+    pub value: f32,
+}
+
+impl Hsv {
+    /// Make a new HSV color.
+    pub fn new(hue: f32, saturation: f32, value: f32) -> Self {
+        Self {
+            hue,
+            saturation,
+            value,
+        }
+    }
+}
+
+impl From<Hsv> for RGB8 {
+    fn from(hsv: Hsv) -> Self {
+        let value = hsv.value.clamp(0.0, 1.0);
+        let chroma = value * hsv.saturation.clamp(0.0, 1.0);
+        from_hue_chroma(hsv.hue, chroma, value - chroma)
+    }
+}
+
+impl From<RGB8> for Hsv {
+    fn from(color: RGB8) -> Self {
+        let (hue, max, min) = hue_max_min(color);
+        let saturation = if max == 0.0 { 0.0 } else { (max - min) / max };
+        Self::new(hue, saturation, max)
+    }
+}
+
+impl From<Hsl> for Hsv {
+    fn from(hsl: Hsl) -> Self {
+        let lightness = hsl.lightness.clamp(0.0, 1.0);
+        let saturation = hsl.saturation.clamp(0.0, 1.0);
+        let value = lightness + saturation * lightness.min(1.0 - lightness);
+        let saturation = if value == 0.0 {
+            0.0
+        } else {
+            2.0 * (1.0 - lightness / value)
+        };
+        Self::new(hsl.hue, saturation, value)
+    }
+}
+
+/// Color as hue, saturation and lightness.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Hsl {
+    /// Hue in degrees.
+    pub hue: f32,
+    /// Saturation.
+    pub saturation: f32,
+    /// Lightness: 0.0 is black, 1.0 is white, and 0.5 is
+    /// the fully saturated color.
+    pub lightness: f32,
+}
+
+impl Hsl {
+    /// Make a new HSL color.
+    pub fn new(hue: f32, saturation: f32, lightness: f32) -> Self {
+        Self {
+            hue,
+            saturation,
+            lightness,
+        }
+    }
+}
+
+impl From<Hsl> for RGB8 {
+    fn from(hsl: Hsl) -> Self {
+        let lightness = hsl.lightness.clamp(0.0, 1.0);
+        let chroma = (1.0 - fabsf(2.0 * lightness - 1.0)) * hsl.saturation.clamp(0.0, 1.0);
+        from_hue_chroma(hsl.hue, chroma, lightness - chroma / 2.0)
+    }
+}
+
+impl From<RGB8> for Hsl {
+    fn from(color: RGB8) -> Self {
+        let (hue, max, min) = hue_max_min(color);
+        let lightness = (max + min) / 2.0;
+        let saturation = if max == min {
+            0.0
+        } else {
+            (max - min) / (1.0 - fabsf(2.0 * lightness - 1.0))
+        };
+        Self::new(hue, saturation, lightness)
+    }
+}
+
+impl From<Hsv> for Hsl {
+    fn from(hsv: Hsv) -> Self {
+        let value = hsv.value.clamp(0.0, 1.0);
+        let lightness = value * (1.0 - hsv.saturation.clamp(0.0, 1.0) / 2.0);
+        let saturation = if lightness == 0.0 || lightness == 1.0 {
+            0.0
+        } else {
+            (value - lightness) / lightness.min(1.0 - lightness)
+        };
+        Self::new(hsv.hue, saturation, lightness)
+    }
+}
+
+/// Fully saturated, full brightness color at `hue` degrees
+/// around the color wheel: 0° is red, 120° green and 240°
+/// blue.
+pub fn wheel(hue: f32) -> RGB8 {
+    Hsv::new(hue, 1.0, 1.0).into()
+}
+
+/// Interpolate linearly between colors `from` and `to`: `t`
+/// of 0.0 gives `from` and 1.0 gives `to`. `t` is clamped
+/// to 0.0..=1.0.
+pub fn lerp(from: RGB8, to: RGB8, t: f32) -> RGB8 {
+    let t = t.clamp(0.0, 1.0);
+    let channel = |a: u8, b: u8| roundf(a as f32 + (b as f32 - a as f32) * t) as u8;
+    RGB8::new(
+        channel(from.r, to.r),
+        channel(from.g, to.g),
+        channel(from.b, to.b),
+    )
+}
+
+/// Interpolate between HSV colors `from` and `to`, taking
+/// the shorter way around the hue wheel: `t` of 0.0 gives
+/// `from` and 1.0 gives `to`. `t` is clamped to 0.0..=1.0.
+pub fn lerp_hsv(from: Hsv, to: Hsv, t: f32) -> Hsv {
+    let t = t.clamp(0.0, 1.0);
+    let mut dh = wrap_hue(to.hue) - wrap_hue(from.hue);
+    if dh > 180.0 {
+        dh -= 360.0;
+    } else if dh < -180.0 {
+        dh += 360.0;
+    }
+    Hsv::new(
+        wrap_hue(from.hue + dh * t),
+        from.saturation + (to.saturation - from.saturation) * t,
+        from.value + (to.value - from.value) * t,
+    )
+}
+
+/// Sample `palette` at position `t` (0.0..=1.0, clamped),
+/// interpolating between neighboring entries. An empty
+/// palette gives black.
+pub fn sample(palette: &[RGB8], t: f32) -> RGB8 {
+    match palette.len() {
+        0 => RGB8::default(),
+        1 => palette[0],
+        n => {
+            let x = t.clamp(0.0, 1.0) * (n - 1) as f32;
+            let i = (x as usize).min(n - 2);
+            lerp(palette[i], palette[i + 1], x - i as f32)
+        }
+    }
+}
+
+/// Named colors and palettes.
+pub mod palette {
+    use super::RGB8;
+
+    pub const BLACK: RGB8 = RGB8::new(0, 0, 0);
+    pub const WHITE: RGB8 = RGB8::new(255, 255, 255);
+    pub const RED: RGB8 = RGB8::new(255, 0, 0);
+    pub const ORANGE: RGB8 = RGB8::new(255, 128, 0);
+    pub const YELLOW: RGB8 = RGB8::new(255, 255, 0);
+    pub const GREEN: RGB8 = RGB8::new(0, 255, 0);
+    pub const CYAN: RGB8 = RGB8::new(0, 255, 255);
+    pub const BLUE: RGB8 = RGB8::new(0, 0, 255);
+    pub const PURPLE: RGB8 = RGB8::new(128, 0, 255);
+    pub const MAGENTA: RGB8 = RGB8::new(255, 0, 255);
+
+    /// Status colors, from good to bad.
+    pub const STATUS: [RGB8; 3] = [GREEN, YELLOW, RED];
+    /// Rainbow, red to violet.
+    pub const RAINBOW: [RGB8; 7] = [
+        RED,
+        ORANGE,
+        YELLOW,
+        GREEN,
+        BLUE,
+        RGB8::new(75, 0, 130),
+        RGB8::new(148, 0, 211),
+    ];
+    /// Fire: black through red and orange to yellow-white.
+    pub const FIRE: [RGB8; 5] = [
+        BLACK,
+        RGB8::new(128, 0, 0),
+        RED,
+        ORANGE,
+        RGB8::new(255, 255, 160),
+    ];
+    /// Ocean: deep blue through cyan to white.
+    pub const OCEAN: [RGB8; 4] = [RGB8::new(0, 0, 64), BLUE, CYAN, WHITE];
+}
@@ -0,0 +1,119 @@
+/*!
+Non-blocking crossfades between two frames of ambient LED
+colors. A [Crossfade] moves every LED linearly from its
+starting color to its ending color over a duration: call
+[Crossfade::update] regularly with the current time in
+milliseconds.
+
+With only 8 bits per channel, a slow fade at low brightness
+visibly steps from one level to the next. A crossfade
+instead works with higher-precision colors and temporally
+dithers them, alternating each channel between the two
+nearest 8-bit levels so that its average over several
+frames is the precise value. The brightness correction is
+applied before dithering, so this also smooths out the dim
+end of corrected fades. Dithering is done frame by frame,
+so it works best with a short frame interval.
+*/
+
+use super::{ws2812, Error as AmbientError, WuKongAmbient, RGB8};
+
+/// Non-blocking crossfade between two frames of `N` LED
+/// colors, in index order.
+#[derive(Debug, Clone)]
+pub struct Crossfade<const N: usize = 4> {
+    from: [RGB8; N],
+    to: [RGB8; N],
+    start: u32,
+    duration: u32,
+    frame_interval: u32,
+    last_frame: Option<u32>,
+    error: [[f32; 3]; N],
+    finished: bool,
+}
+
+impl<const N: usize> Crossfade<N> {
+    /// Default time between frames in milliseconds.
+    pub const FRAME_INTERVAL: u32 = 4;
+
+    /// Start a crossfade at time `now` from colors `from` to
+    /// colors `to`, taking `duration` milliseconds. A zero
+    /// duration goes straight to `to`.
+    pub fn new(from: [RGB8; N], to: [RGB8; N], duration: u32, now: u32) -> Self {
+        Self {
+            from,
+            to,
+            start: now,
+            duration,
+            frame_interval: Self::FRAME_INTERVAL,
+            last_frame: None,
+            error: [[0.0; 3]; N],
+            finished: false,
+        }
+    }
+
+    /// The starting colors.
+    pub fn from(&self) -> &[RGB8; N] {
+        &self.from
+    }
+
+    /// The ending colors.
+    pub fn to(&self) -> &[RGB8; N] {
+        &self.to
+    }
+
+    /// True once the ending colors have been sent.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Set the minimum time between frames in
+    /// milliseconds.
+    pub fn set_frame_interval(&mut self, frame_interval: u32) {
+        self.frame_interval = frame_interval;
+    }
+
+    /// Send the crossfade frame for time `now` to `ambient`
+    /// if the frame interval has passed since the last one.
+    /// Once the duration is over, the exact ending colors
+    /// are sent and the crossfade is finished. Call this
+    /// regularly; apart from the LED transfer itself it does
+    /// not block. If `ambient` is in deferred mode, frames
+    /// are only staged, without dithering.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LED transfer fails.
+    pub fn update<P>(
+        &mut self,
+        ambient: &mut WuKongAmbient<P, N>,
+        now: u32,
+    ) -> Result<(), AmbientError<P>>
+    where
+        P: ws2812::Instance,
+    {
+        if self.finished {
+            return Ok(());
+        }
+        if let Some(last) = self.last_frame {
+            if now.wrapping_sub(last) < self.frame_interval {
+                return Ok(());
+            }
+        }
+        let elapsed = now.wrapping_sub(self.start);
+        if elapsed >= self.duration {
+            ambient.set_colors(self.to)?;
+            self.finished = true;
+            return Ok(());
+        }
+        let t = elapsed as f32 / self.duration as f32;
+        let lerp = |a: u8, b: u8| a as f32 + (b as f32 - a as f32) * t;
+        let colors: [[f32; 3]; N] = core::array::from_fn(|i| {
+            let (from, to) = (self.from[i], self.to[i]);
+            [lerp(from.r, to.r), lerp(from.g, to.g), lerp(from.b, to.b)]
+        });
+        ambient.send_dithered(&colors, &mut self.error)?;
+        self.last_frame = Some(now);
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+/*!
+Gauge display on the four corner LEDs: show a value such as
+battery level, distance to an obstacle, or progress through
+a task. A [Gauge] maps a value in 0.0..=1.0, or in any
+range, onto the LEDs in a configurable [FillOrder]. Each LED
+covers a quarter of the scale, and the LED at the end of the
+fill is lit partway, so the gauge moves smoothly.
+
+Lit LEDs take their colors from a gradient, sampled either
+at the gauge value or at each LED's position along the
+gauge; unlit parts show a background color, black by
+default.
+*/
+
+use super::{color, ws2812, Corner, Error as AmbientError, WuKongAmbient, RGB8};
+
+/// Order in which corners fill as the gauge value rises.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillOrder {
+    /// Clockwise seen from above, starting at the given
+    /// corner.
+    Clockwise(Corner),
+    /// Counterclockwise seen from above, starting at the
+    /// given corner.
+    Counterclockwise(Corner),
+    /// The given corners, first to last.
+    Custom([Corner; 4]),
+}
+
+impl FillOrder {
+    /// The corners, first to fill to last.
+    pub fn corners(self) -> [Corner; 4] {
+        match self {
+            FillOrder::Clockwise(start) => {
+                core::array::from_fn(|i| Corner::ALL[(start as usize + i) % 4])
+            }
+            FillOrder::Counterclockwise(start) => {
+                core::array::from_fn(|i| Corner::ALL[(start as usize + 4 - i) % 4])
+            }
+            FillOrder::Custom(corners) => corners,
+        }
+    }
+}
+
+/// Where the gradient is sampled for a lit LED.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shading {
+    /// All lit LEDs have the gradient color at the gauge
+    /// value, as for a battery level that turns from green
+    /// to red as it drops.
+    Value,
+    /// Each LED has the gradient color at its own position
+    /// along the gauge, as for a bar that shades from green
+    /// to red along its length.
+    Position,
+}
+
+/// Gauge display settings.
+#[derive(Debug, Clone, Copy)]
+pub struct Gauge<'a> {
+    gradient: &'a [RGB8],
+    order: FillOrder,
+    shading: Shading,
+    background: RGB8,
+}
+
+impl<'a> Gauge<'a> {
+    /// A gauge shading lit LEDs by value through `gradient`
+    /// (see [color::sample]), filling clockwise from front
+    /// left, on a black background.
+    pub const fn new(gradient: &'a [RGB8]) -> Self {
+        Self {
+            gradient,
+            order: FillOrder::Clockwise(Corner::FrontLeft),
+            shading: Shading::Value,
+            background: RGB8::new(0, 0, 0),
+        }
+    }
+
+    /// This gauge filling in the given `order`.
+    pub const fn with_order(self, order: FillOrder) -> Self {
+        Self { order, ..self }
+    }
+
+    /// This gauge sampling its gradient as given by
+    /// `shading`.
+    pub const fn with_shading(self, shading: Shading) -> Self {
+        Self { shading, ..self }
+    }
+
+    /// This gauge showing `background` where it is not
+    /// lit.
+    pub const fn with_background(self, background: RGB8) -> Self {
+        Self { background, ..self }
+    }
+
+    /// Colors showing `value` (0.0..=1.0, clamped), in
+    /// [Corner::ALL] order.
+    pub fn frame(&self, value: f32) -> [RGB8; 4] {
+        // NaN shows as empty.
+        let value = if value > 0.0 { value.min(1.0) } else { 0.0 };
+        let mut colors = [self.background; 4];
+        for (i, corner) in self.order.corners().into_iter().enumerate() {
+            let fill = (value * 4.0 - i as f32).clamp(0.0, 1.0);
+            let t = match self.shading {
+                Shading::Value => value,
+                Shading::Position => i as f32 / 3.0,
+            };
+            let lit = color::sample(self.gradient, t);
+            colors[corner as usize] = color::lerp(self.background, lit, fill);
+        }
+        colors
+    }
+
+    /// Colors showing `value` on the scale `min..=max`, in
+    /// [Corner::ALL] order. `min` shows as empty and `max`
+    /// as full; `min` may be greater than `max`, for
+    /// example to fill as an obstacle gets nearer.
+    pub fn frame_range(&self, value: f32, min: f32, max: f32) -> [RGB8; 4] {
+        self.frame((value - min) / (max - min))
+    }
+
+    /// Show `value` (0.0..=1.0, clamped) on `ambient`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LED transfer fails.
+    pub fn show<P>(&self, ambient: &mut WuKongAmbient<P>, value: f32) -> Result<(), AmbientError<P>>
+    where
+        P: ws2812::Instance,
+    {
+        ambient.set_corner_colors(self.frame(value))
+    }
+
+    /// Show `value` on the scale `min..=max` on `ambient`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LED transfer fails.
+    pub fn show_range<P>(
+        &self,
+        ambient: &mut WuKongAmbient<P>,
+        value: f32,
+        min: f32,
+        max: f32,
+    ) -> Result<(), AmbientError<P>>
+    where
+        P: ws2812::Instance,
+    {
+        ambient.set_corner_colors(self.frame_range(value, min, max))
+    }
+}
@@ -0,0 +1,505 @@
+/*!
+Drivers for a chain of WS2812-family LEDs, which need
+precise fast timing. There are two backends:
+
+* [Ws2812] uses an nRF52833 PWM unit, which makes it easy
+  to get the timing right. Each LED color bit is sent as one
+  PWM sample, so the DMA buffer holds 24 samples per LED.
+  This is adapted from the `ws2812-nrf52833-pwm` crate, with
+  the chain length given as a number of LEDs rather than a
+  number of samples.
+
+* [Ws2812Spim] uses an nRF52833 SPIM unit, leaving the PWM
+  units free for other jobs such as the buzzer. Only the
+  SPIM data output is used: each LED color bit is sent as
+  ten bits at 8 MHz, giving the same 1.25µs bit time as the
+  PWM backend. A 0 is 375ns high then 875ns low, and a 1 is
+  750ns high then 500ns low, all within the ±150ns
+  tolerances of the WS2812B datasheet. (The common 4 MHz
+  encoding, four SPIM bits per color bit, gives a 1 only
+  250ns low, which is out of spec.) This backend has not
+  been tested on Wukong hardware. Note that SPIM0–2 share
+  their hardware with the TWIM and other serial units of
+  the same number; the MB2 uses TWIM0 for its internal I2C
+  bus (and the Wukong bus), so SPIM3 or SPIM2 is usually the
+  one to pick.
+
+The backend is chosen by the peripheral handed to the
+driver: [Instance] is implemented for all PWM and SPIM
+units.
+
+The PWM backend can also write without blocking
+([NonBlocking]): the PWM plays the frame from a
+[FrameBuffer] by DMA while the program gets on with other
+work, and hands the buffer back when it is done.
+*/
+
+use embedded_dma as dma;
+use embedded_hal::spi::{SpiBus, MODE_0};
+use nrf52833_hal::{gpio, pac, pwm, spim};
+use smart_leds::RGB8;
+
+/// Error during WS2812 driver operation.
+#[allow(clippy::enum_variant_names)]
+pub enum Error<P> {
+    /// PWM error.
+    PwmError(pwm::Error, P, pwm::Pins),
+    /// SPIM error.
+    SpimError(spim::Error),
+    /// A non-blocking write is in progress.
+    Busy,
+}
+
+impl<P> core::fmt::Debug for Error<P> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::PwmError(err, _, _) => write!(f, "pwm error: {:?}", err),
+            Error::SpimError(err) => write!(f, "spim error: {:?}", err),
+            Error::Busy => write!(f, "busy"),
+        }
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A peripheral that can drive a WS2812 chain: any PWM or
+/// SPIM unit.
+pub trait Instance: sealed::Sealed + Sized {
+    /// Driver for a chain of `N` LEDs.
+    type Driver<const N: usize>;
+
+    /// Set up a driver for a chain of `N` LEDs on `pin`.
+    fn driver<const N: usize, PinMode>(self, pin: gpio::Pin<PinMode>) -> Self::Driver<N>;
+
+    /// Send `colors` to the chain, blocking until the
+    /// transfer is done.
+    fn write<const N: usize>(
+        driver: &mut Self::Driver<N>,
+        colors: &[RGB8; N],
+    ) -> Result<(), Error<Self>>;
+}
+
+/// A peripheral that can drive a WS2812 chain without
+/// blocking: any PWM unit. See [Ws2812::start_write].
+pub trait NonBlocking: Instance {
+    /// Start sending `colors` to the chain using `frame`,
+    /// without waiting for the transfer.
+    fn start_write<const N: usize>(
+        driver: &mut Self::Driver<N>,
+        colors: &[RGB8; N],
+        frame: &'static mut FrameBuffer<N>,
+    ) -> Result<(), (Error<Self>, &'static mut FrameBuffer<N>)>;
+
+    /// True if a non-blocking write is in progress.
+    fn is_busy<const N: usize>(driver: &Self::Driver<N>) -> bool;
+
+    /// Return the frame buffer of a finished non-blocking
+    /// write.
+    fn poll<const N: usize>(driver: &mut Self::Driver<N>) -> Option<&'static mut FrameBuffer<N>>;
+}
+
+macro_rules! pwm_instances {
+    ($($pwm:ident),*) => {$(
+        impl sealed::Sealed for pac::$pwm {}
+
+        impl Instance for pac::$pwm {
+            type Driver<const N: usize> = Ws2812<Self, N>;
+
+            fn driver<const N: usize, PinMode>(self, pin: gpio::Pin<PinMode>) -> Self::Driver<N> {
+                Ws2812::new(self, pin)
+            }
+
+            fn write<const N: usize>(
+                driver: &mut Self::Driver<N>,
+                colors: &[RGB8; N],
+            ) -> Result<(), Error<Self>> {
+                driver.write(colors)
+            }
+        }
+
+        impl NonBlocking for pac::$pwm {
+            fn start_write<const N: usize>(
+                driver: &mut Self::Driver<N>,
+                colors: &[RGB8; N],
+                frame: &'static mut FrameBuffer<N>,
+            ) -> Result<(), (Error<Self>, &'static mut FrameBuffer<N>)> {
+                driver.start_write(colors, frame)
+            }
+
+            fn is_busy<const N: usize>(driver: &Self::Driver<N>) -> bool {
+                driver.is_busy()
+            }
+
+            fn poll<const N: usize>(
+                driver: &mut Self::Driver<N>,
+            ) -> Option<&'static mut FrameBuffer<N>> {
+                driver.poll()
+            }
+        }
+    )*};
+}
+
+pwm_instances!(PWM0, PWM1, PWM2, PWM3);
+
+macro_rules! spim_instances {
+    ($($spim:ident),*) => {$(
+        impl sealed::Sealed for pac::$spim {}
+
+        impl Instance for pac::$spim {
+            type Driver<const N: usize> = Ws2812Spim<Self, N>;
+
+            fn driver<const N: usize, PinMode>(self, pin: gpio::Pin<PinMode>) -> Self::Driver<N> {
+                Ws2812Spim::new(self, pin)
+            }
+
+            fn write<const N: usize>(
+                driver: &mut Self::Driver<N>,
+                colors: &[RGB8; N],
+            ) -> Result<(), Error<Self>> {
+                driver.write(colors)
+            }
+        }
+    )*};
+}
+
+spim_instances!(SPIM0, SPIM1, SPIM2, SPIM3);
+
+/// GRB bits of `color`, in the order WS2812s take them.
+fn grb_bits(color: &RGB8) -> u32 {
+    ((color.g as u32) << 16) | ((color.r as u32) << 8) | (color.b as u32)
+}
+
+/// WS2812 0-bit high time in ns.
+const T0H_NS: u32 = 400;
+/// WS2812 1-bit high time in ns.
+const T1H_NS: u32 = 800;
+/// WS2812 total bit time in ns.
+const FRAME_NS: u32 = 1250;
+/// WS2812 frame reset time in µs (minimum 250µs for some BC, plus slop).
+const RESET_TIME: u32 = 270;
+
+/// PWM clock in MHz.
+const PWM_CLOCK: u32 = 16;
+
+/// Convert nanoseconds to PWM ticks, rounding.
+const fn to_ticks(ns: u32) -> u32 {
+    (ns * PWM_CLOCK + 500) / 1000
+}
+
+/// WS2812 frame reset time in PWM periods (one period per
+/// LED color bit), as counted by the sequence end delay.
+const RESET_PERIODS: u32 = RESET_TIME * 1000 / FRAME_NS;
+
+/// Samples for PWM array, with flip bits.
+const BITS: [u16; 2] = [
+    // 0-bit high time in ticks.
+    to_ticks(T0H_NS) as u16 | 0x8000,
+    // 1-bit high time in ticks.
+    to_ticks(T1H_NS) as u16 | 0x8000,
+];
+/// Total PWM period in ticks.
+const PWM_PERIOD: u16 = to_ticks(FRAME_NS) as u16;
+
+/// PWM samples for `N` LEDs.
+type Seq<const N: usize> = [[u16; 24]; N];
+
+/// Low sample sent before the LED data, so that the line is
+/// low for the frame reset when the data starts.
+const PREAMBLE: u16 = 0x8000;
+
+/// PWM sample buffer for a frame of `N` LEDs. A buffer in
+/// a `static` is needed for
+/// [non-blocking writes](Ws2812::start_write), for example
+/// from `cortex_m::singleton!`.
+#[repr(C)]
+pub struct FrameBuffer<const N: usize> {
+    preamble: [u16; 1],
+    samples: Seq<N>,
+}
+
+impl<const N: usize> FrameBuffer<N> {
+    /// A new frame buffer.
+    pub const fn new() -> Self {
+        Self {
+            preamble: [PREAMBLE],
+            samples: [[0; 24]; N],
+        }
+    }
+
+    /// Fill the buffer with the samples for `colors`.
+    fn encode(&mut self, colors: &[RGB8; N]) {
+        for (color, samples) in colors.iter().zip(self.samples.iter_mut()) {
+            let bits = grb_bits(color);
+            for (i, sample) in samples.iter_mut().enumerate() {
+                let b = (bits >> (24 - i - 1)) & 1;
+                *sample = BITS[b as usize];
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for FrameBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// DMA view of the preamble of a frame buffer, which must
+/// stay put until the transfer is done.
+struct PreambleRef<const N: usize>(*mut FrameBuffer<N>);
+
+unsafe impl<const N: usize> dma::ReadBuffer for PreambleRef<N> {
+    type Word = u16;
+    unsafe fn read_buffer(&self) -> (*const Self::Word, usize) {
+        (core::ptr::addr_of!((*self.0).preamble) as *const u16, 1)
+    }
+}
+
+/// DMA view of the LED samples of a frame buffer, which
+/// must stay put until the transfer is done.
+struct SamplesRef<const N: usize>(*mut FrameBuffer<N>);
+
+unsafe impl<const N: usize> dma::ReadBuffer for SamplesRef<N> {
+    type Word = u16;
+    unsafe fn read_buffer(&self) -> (*const Self::Word, usize) {
+        (core::ptr::addr_of!((*self.0).samples) as *const u16, N * 24)
+    }
+}
+
+/// PWM sequence of a transfer in progress.
+type Transfer<PWM, const N: usize> = pwm::PwmSeq<PWM, PreambleRef<N>, SamplesRef<N>>;
+
+/// Driver for a chain of `N` WS2812-family LEDs using PWM.
+pub struct Ws2812<PWM, const N: usize>
+where
+    PWM: pwm::Instance,
+{
+    pwm: Option<pwm::Pwm<PWM>>,
+    transfer: Option<Transfer<PWM, N>>,
+    buf: FrameBuffer<N>,
+}
+
+impl<PWM, const N: usize> Ws2812<PWM, N>
+where
+    PWM: pwm::Instance,
+{
+    /// Set up WS2812 chain with PWM and an output pin.
+    pub fn new<PinMode>(pwm: PWM, pin: gpio::Pin<PinMode>) -> Self {
+        // Use high drive to get faster rise/fall times. Probably unnecessary.
+        let pin = pin
+            .into_push_pull_output_drive(gpio::Level::Low, gpio::DriveConfig::HighDrive0HighDrive1);
+        let pwm = pwm::Pwm::new(pwm);
+        pwm
+            // output the waveform on the LED pin
+            .set_output_pin(pwm::Channel::C0, pin)
+            // Prescaler set for 16MHz.
+            .set_prescaler(pwm::Prescaler::Div1)
+            // Configure for up counter mode.
+            .set_counter_mode(pwm::CounterMode::Up)
+            // Read duty cycle values from sequence.
+            .set_load_mode(pwm::LoadMode::Common)
+            // Set maximum duty cycle = PWM period in ticks.
+            .set_max_duty(PWM_PERIOD);
+
+        Self {
+            pwm: Some(pwm),
+            transfer: None,
+            buf: FrameBuffer::new(),
+        }
+    }
+
+    /// Start sending the samples in `frame`, which must not
+    /// move or be touched until the transfer is done.
+    fn start(&mut self, frame: *mut FrameBuffer<N>) -> Result<Transfer<PWM, N>, Error<PWM>> {
+        let pwm = self.pwm.take().ok_or(Error::Busy)?;
+        pwm
+            // Be sure to be advancing the thing.
+            .set_step_mode(pwm::StepMode::Auto)
+            // Set no delay between samples.
+            .set_seq_refresh(pwm::Seq::Seq0, 0)
+            // Set reset delay at end of sequence 0.
+            .set_seq_end_delay(pwm::Seq::Seq0, RESET_PERIODS)
+            // Set no delay between samples.
+            .set_seq_refresh(pwm::Seq::Seq1, 0)
+            // Set no delay at end of sequence 1.
+            .set_seq_end_delay(pwm::Seq::Seq1, 0)
+            // Enable sample channel.
+            .enable_channel(pwm::Channel::C0)
+            // Enable sample group.
+            .enable_group(pwm::Group::G0)
+            // Run this waveform once.
+            .repeat(1)
+            // Enable now.
+            .enable();
+        let seq = pwm
+            .load(Some(PreambleRef(frame)), Some(SamplesRef(frame)), false)
+            .map_err(|(err, pwm, _, _)| {
+                let (pwm, pins) = pwm.free();
+                Error::PwmError(err, pwm, pins)
+            })?;
+
+        seq.reset_event(pwm::PwmEvent::LoopsDone);
+        seq.start_seq(pwm::Seq::Seq0);
+        Ok(seq)
+    }
+
+    /// Stop the finished transfer `seq`, returning its
+    /// frame buffer.
+    fn finish(&mut self, seq: Transfer<PWM, N>) -> *mut FrameBuffer<N> {
+        seq.stop();
+        let (_, samples, pwm) = seq.split();
+        pwm.stop();
+        self.pwm = Some(pwm);
+        // Always loaded.
+        samples.unwrap().0
+    }
+
+    /// Send `colors` to the chain, blocking until the
+    /// transfer is done.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if a non-blocking write is in
+    ///   progress.
+    /// * Returns an error if the PWM sequence cannot be
+    ///   loaded. The driver is unusable afterward.
+    pub fn write(&mut self, colors: &[RGB8; N]) -> Result<(), Error<PWM>> {
+        if self.pwm.is_none() {
+            return Err(Error::Busy);
+        }
+        self.buf.encode(colors);
+        let frame: *mut FrameBuffer<N> = &mut self.buf;
+        // The buffer is borrowed until the transfer is done.
+        let seq = self.start(frame)?;
+        while !seq.is_event_triggered(pwm::PwmEvent::LoopsDone) {}
+        self.finish(seq);
+        Ok(())
+    }
+
+    /// Start sending `colors` to the chain using `frame`,
+    /// returning without waiting for the transfer. Call
+    /// [Ws2812::poll] to find out when it is done and get
+    /// `frame` back.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if a non-blocking write is in
+    ///   progress.
+    /// * Returns an error if the PWM sequence cannot be
+    ///   loaded. The driver is unusable afterward.
+    ///
+    /// `frame` is handed back with the error.
+    pub fn start_write(
+        &mut self,
+        colors: &[RGB8; N],
+        frame: &'static mut FrameBuffer<N>,
+    ) -> Result<(), (Error<PWM>, &'static mut FrameBuffer<N>)> {
+        if self.pwm.is_none() {
+            return Err((Error::Busy, frame));
+        }
+        frame.encode(colors);
+        let frame: *mut FrameBuffer<N> = frame;
+        match self.start(frame) {
+            Ok(seq) => {
+                self.transfer = Some(seq);
+                Ok(())
+            }
+            // SAFETY: the transfer never started, so `frame`
+            // is ours again.
+            Err(err) => Err((err, unsafe { &mut *frame })),
+        }
+    }
+
+    /// True if a non-blocking write is in progress.
+    pub fn is_busy(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    /// If a non-blocking write has finished, stop it and
+    /// return its frame buffer. Returns `None` if the write
+    /// is still in progress or there is none.
+    pub fn poll(&mut self) -> Option<&'static mut FrameBuffer<N>> {
+        let seq = self.transfer.as_ref()?;
+        if !seq.is_event_triggered(pwm::PwmEvent::LoopsDone) {
+            return None;
+        }
+        let seq = self.transfer.take()?;
+        let frame = self.finish(seq);
+        // SAFETY: `frame` came from the `&'static mut` given
+        // to `start_write`, and the DMA is done with it.
+        Some(unsafe { &mut *frame })
+    }
+}
+
+/// SPIM bits sent for each LED color bit.
+const SPIM_BITS_PER_BIT: u32 = 10;
+
+/// SPIM bits for a 0 and a 1 LED color bit at 8 MHz: 3 or
+/// 6 bits (375ns or 750ns) high, then low.
+const SPIM_BITS: [u32; 2] = [0b11100_00000, 0b11111_10000];
+
+/// SPIM bytes for one LED.
+const SPIM_LED_BYTES: usize = (24 * SPIM_BITS_PER_BIT / 8) as usize;
+
+/// Low bytes sent before the LED data for the WS2812 frame
+/// reset: 300µs at 8 MHz.
+const SPIM_RESET_BYTES: usize = 300;
+
+/// Driver for a chain of `N` WS2812-family LEDs using SPIM.
+pub struct Ws2812Spim<SPIM, const N: usize> {
+    spim: spim::Spim<SPIM>,
+    buf: [[u8; SPIM_LED_BYTES]; N],
+}
+
+impl<SPIM, const N: usize> Ws2812Spim<SPIM, N>
+where
+    SPIM: spim::Instance,
+{
+    /// Set up WS2812 chain with SPIM and an output pin.
+    pub fn new<PinMode>(spim: SPIM, pin: gpio::Pin<PinMode>) -> Self {
+        // Use high drive to get faster rise/fall times. Probably unnecessary.
+        let pin = pin
+            .into_push_pull_output_drive(gpio::Level::Low, gpio::DriveConfig::HighDrive0HighDrive1);
+        let pins = spim::Pins {
+            sck: None,
+            mosi: Some(pin),
+            miso: None,
+        };
+        let spim = spim::Spim::new(spim, pins, spim::Frequency::M8, MODE_0, 0);
+        Self {
+            spim,
+            buf: [[0; SPIM_LED_BYTES]; N],
+        }
+    }
+
+    /// Send `colors` to the chain, blocking until the
+    /// transfer is done.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SPIM transfer fails.
+    pub fn write(&mut self, colors: &[RGB8; N]) -> Result<(), Error<SPIM>> {
+        for (color, bytes) in colors.iter().zip(self.buf.iter_mut()) {
+            let bits = grb_bits(color);
+            // Shift the SPIM bits through `acc`, most
+            // significant first, a byte at a time.
+            let (mut acc, mut len) = (0u32, 0);
+            let mut bytes = bytes.iter_mut();
+            for i in (0..24).rev() {
+                acc = (acc << SPIM_BITS_PER_BIT) | SPIM_BITS[(bits >> i) as usize & 1];
+                len += SPIM_BITS_PER_BIT;
+                while len >= 8 {
+                    len -= 8;
+                    if let Some(byte) = bytes.next() {
+                        *byte = (acc >> len) as u8;
+                    }
+                }
+            }
+        }
+        // The data ends low, so the line idles low.
+        SpiBus::write(&mut self.spim, &[0; SPIM_RESET_BYTES]).map_err(Error::SpimError)?;
+        SpiBus::write(&mut self.spim, self.buf.as_flattened()).map_err(Error::SpimError)
+    }
+}
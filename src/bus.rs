@@ -11,11 +11,15 @@ pub mod motor;
 pub mod servo;
 
 #[cfg(feature = "mood_lights")]
-pub use mood_lights::MoodLights;
+pub use mood_lights::{MoodLightController, MoodLights};
 #[cfg(feature = "motor")]
 pub use motor::Motor;
 #[cfg(feature = "servo")]
-pub use servo::{Servo, ServoAngle, ServoConfig};
+pub use servo::{
+    ConfiguredServo, Pose, Servo, ServoAngle, ServoConfig, ServoGroup, ServoLayout, ServoLink,
+};
+
+use core::marker::PhantomData;
 
 use nrf52833_hal::{gpio, pac::twim0, twim};
 
@@ -48,16 +52,29 @@ impl core::fmt::Debug for Error {
     }
 }
 
-pub struct WuKongBus<TWIM> {
+/// Servo layout of a bus that has not had one installed: no
+/// servos can be commanded through it.
+#[derive(Debug, Clone, Copy)]
+pub enum NoServos {}
+
+/// Wukong I2C bus driver. The `L` parameter is the servo
+/// layout installed on the bus, if any: see the `servo`
+/// module.
+pub struct WuKongBus<TWIM, L = NoServos> {
     i2c: twim::Twim<TWIM>,
+    #[cfg(feature = "servo")]
+    active_servos: u8,
+    servo_layout: PhantomData<L>,
+}
+
+impl<TWIM, L> WuKongBus<TWIM, L> {
+    pub const I2C_ADDR: u8 = 0x10;
 }
 
 impl<TWIM> WuKongBus<TWIM>
 where
     TWIM: twim::Instance,
 {
-    pub const I2C_ADDR: u8 = 0x10;
-
     /// Make a new I2C bus driver. Rquires a TWIM for
     /// `i2c`. Takes ownership of the specific MB2 external
     /// `scl` and `sda` pins, so can only be instantiated
@@ -73,6 +90,11 @@ where
         };
         let freq = twim0::frequency::FREQUENCY_A::K100;
         let i2c = twim::Twim::new(i2c, pins, freq);
-        Self { i2c }
+        Self {
+            i2c,
+            #[cfg(feature = "servo")]
+            active_servos: 0,
+            servo_layout: PhantomData,
+        }
     }
 }
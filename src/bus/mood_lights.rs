@@ -5,9 +5,15 @@ the I2C bus. The LEDs move in unison, and can be set either
 to "breathe" ("breath" in the Wukong documentation) or to a
 specific intensity. The interface here mostly follows that
 of the MicroPython and PXT implementations.
+
+The [effects] module builds fades, blinks and other patterns
+in software on top of [MoodLightController].
 */
 
+pub mod effects;
+
 use crate::bus;
+use crate::correction::Correction;
 
 use embedded_hal::delay;
 use nrf52833_hal::twim;
@@ -17,6 +23,10 @@ use nrf52833_hal::twim;
 pub enum Error {
     /// Attempted to set intensity too high.
     Overintensity(u8),
+    /// Attempted to set breath depth too high.
+    Overdepth(u8),
+    /// Effect has a time too short to show or an empty table.
+    InvalidEffect,
 }
 
 impl From<Error> for bus::Error {
@@ -30,22 +40,88 @@ impl From<Error> for bus::Error {
 pub enum MoodLights {
     /// Turned off (default).
     Off,
-    /// "Breathing" with a period of a couple of seconds.
-    Breath,
+    /// "Breathing" with given depth (0..=100). The depth is
+    /// scaled into the firmware's breath parameter, which is
+    /// sent where [MoodLights::Intensity] sends its intensity.
+    /// What the firmware does with that parameter has not
+    /// been checked against its source or on hardware: it is
+    /// assumed to set how bright the lights get at the peak
+    /// of each breath.
+    ///
+    /// The breath rate is fixed by the firmware. No command
+    /// to change it is known: the `0x11` frame argument is `0`
+    /// for breathing and `160` otherwise, as sent by the PXT
+    /// implementation, and appears to select the mode rather
+    /// than a rate.
+    Breath(u8),
     /// On with given intensity (0..=100).
     Intensity(u8),
 }
 
-impl<TWIM> bus::WuKongBus<TWIM>
+impl MoodLights {
+    /// Breathing at full depth, as done by the MicroPython
+    /// and PXT implementations.
+    pub const BREATH: MoodLights = MoodLights::Breath(100);
+
+    /// Firmware breath parameter for full depth: the value
+    /// the PXT implementation always sends. Whether larger
+    /// values mean anything to the firmware is unknown.
+    const FULL_BREATH: u16 = 150;
+
+    /// This mode with its intensity passed through
+    /// `correction`.
+    fn corrected(self, correction: Correction) -> Self {
+        match self {
+            MoodLights::Intensity(intensity) => {
+                MoodLights::Intensity(correction.apply(intensity, 100))
+            }
+            mood_lights => mood_lights,
+        }
+    }
+
+    /// The two I2C frames that set this mode. The protocol
+    /// requires a pause of [MoodLightController::GAP]
+    /// milliseconds between them.
+    pub(crate) fn frames(self) -> Result<[[u8; 4]; 2], Error> {
+        let frames = match self {
+            MoodLights::Breath(depth) => {
+                if depth > 100 {
+                    return Err(Error::Overdepth(depth));
+                }
+                let depth = (depth as u16 * MoodLights::FULL_BREATH + 50) / 100;
+                [[0x11, 0, 0, 0], [0x12, depth as u8, 0, 0]]
+            }
+            mood_lights => {
+                let intensity = match mood_lights {
+                    MoodLights::Off => 0,
+                    MoodLights::Intensity(intensity) => {
+                        if intensity > 100 {
+                            return Err(Error::Overintensity(intensity));
+                        }
+                        intensity
+                    }
+                    MoodLights::Breath(_) => unreachable!(),
+                };
+                [[0x12, intensity, 0, 0], [0x11, 160, 0, 0]]
+            }
+        };
+        Ok(frames)
+    }
+}
+
+impl<TWIM, L> bus::WuKongBus<TWIM, L>
 where
     TWIM: twim::Instance,
 {
     /// Set the `mood_lights` to the given mode. A `delay` unit must
-    /// be borrowed to properly implement the protocol.
+    /// be borrowed to properly implement the protocol. See
+    /// [MoodLightController] for a non-blocking alternative.
     ///
     /// # Errors
     ///
-    /// Returns an error if an I2C write fails.
+    /// * Returns an error if the intensity or breath depth
+    ///   is out of range.
+    /// * Returns an error if an I2C write fails.
     pub fn set_mood_lights<Delay>(
         &mut self,
         delay: &mut Delay,
@@ -54,35 +130,148 @@ where
     where
         Delay: delay::DelayNs,
     {
-        match mood_lights {
-            MoodLights::Breath => {
-                let buf = [0x11, 0, 0, 0];
-                self.i2c.write(Self::I2C_ADDR, &buf)?;
+        let [first, second] = mood_lights.frames()?;
+        self.i2c.write(Self::I2C_ADDR, &first)?;
 
-                delay.delay_ms(100);
+        delay.delay_ms(MoodLightController::GAP);
 
-                let buf = [0x12, 150, 0, 0];
-                self.i2c.write(Self::I2C_ADDR, &buf)?;
-            }
-            mood_lights => {
-                let intensity = match mood_lights {
-                    MoodLights::Off => 0,
-                    MoodLights::Intensity(intensity) => {
-                        if intensity > 100 {
-                            return Err(Error::Overintensity(intensity).into());
-                        }
-                        intensity
-                    }
-                    MoodLights::Breath => unreachable!(),
-                };
-                let buf = [0x12, intensity, 0, 0];
-                self.i2c.write(Self::I2C_ADDR, &buf)?;
+        self.i2c.write(Self::I2C_ADDR, &second)?;
+        Ok(())
+    }
+}
 
-                delay.delay_ms(100);
+/// Non-blocking mood light driver. Setting a mode sends the
+/// first half of the protocol immediately; the second half
+/// is sent by a later call to [MoodLightController::poll]
+/// once the required gap has passed.
+///
+/// Times are in milliseconds, supplied by the caller from a
+/// free-running timer; they are allowed to wrap.
+///
+/// The controller can optionally apply a brightness
+/// [Correction] to intensities, so that fades look smooth.
+#[derive(Debug, Clone)]
+pub struct MoodLightController {
+    mode: MoodLights,
+    correction: Correction,
+    queued: Option<MoodLights>,
+    pending: Option<[u8; 4]>,
+    last_write: Option<u32>,
+}
 
-                let buf = [0x11, 160, 0, 0];
-                self.i2c.write(Self::I2C_ADDR, &buf)?;
-            }
+impl Default for MoodLightController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MoodLightController {
+    /// Gap in milliseconds the protocol needs between the
+    /// I2C writes that set a mode.
+    pub const GAP: u32 = 100;
+
+    /// Make a new controller. The lights are assumed to be
+    /// off.
+    pub const fn new() -> Self {
+        Self {
+            mode: MoodLights::Off,
+            correction: Correction::Linear,
+            queued: None,
+            pending: None,
+            last_write: None,
+        }
+    }
+
+    /// The most recently requested mode. This may not have
+    /// been fully sent yet: see
+    /// [MoodLightController::is_settled].
+    pub fn mode(&self) -> MoodLights {
+        self.mode
+    }
+
+    /// The brightness correction applied to intensities.
+    pub fn correction(&self) -> Correction {
+        self.correction
+    }
+
+    /// Set the brightness `correction` applied to
+    /// intensities. This takes effect when the next mode is
+    /// set.
+    pub fn set_correction(&mut self, correction: Correction) {
+        self.correction = correction;
+    }
+
+    /// True if the current mode has been completely sent.
+    pub fn is_settled(&self) -> bool {
+        self.queued.is_none() && self.pending.is_none()
+    }
+
+    /// True if a write sent at time `now` would honor the
+    /// protocol gap.
+    fn ready(&self, now: u32) -> bool {
+        self.last_write
+            .map(|last| now.wrapping_sub(last) >= Self::GAP)
+            .unwrap_or(true)
+    }
+
+    /// Set the mood lights to the given mode at time `now`.
+    /// The first protocol frame is sent now if the bus
+    /// timing allows; the rest is left to
+    /// [MoodLightController::poll]. A mode change that has
+    /// not finished sending is abandoned in favor of the new
+    /// one.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if the intensity or breath depth
+    ///   is out of range.
+    /// * Returns an error if an I2C write fails.
+    pub fn set<TWIM, L>(
+        &mut self,
+        bus: &mut bus::WuKongBus<TWIM, L>,
+        mood_lights: MoodLights,
+        now: u32,
+    ) -> Result<(), bus::Error>
+    where
+        TWIM: twim::Instance,
+    {
+        mood_lights.frames()?;
+        self.mode = mood_lights;
+        self.queued = Some(mood_lights);
+        self.pending = None;
+        self.poll(bus, now)
+    }
+
+    /// Send whatever the protocol allows at time `now`.
+    /// Call this regularly until the controller is settled;
+    /// it does not block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an I2C write fails. The write
+    /// will be retried on the next call.
+    pub fn poll<TWIM, L>(
+        &mut self,
+        bus: &mut bus::WuKongBus<TWIM, L>,
+        now: u32,
+    ) -> Result<(), bus::Error>
+    where
+        TWIM: twim::Instance,
+    {
+        if !self.ready(now) {
+            return Ok(());
+        }
+        if let Some(mood_lights) = self.queued {
+            let [first, second] = mood_lights.corrected(self.correction).frames()?;
+            bus.i2c.write(bus::WuKongBus::<TWIM, L>::I2C_ADDR, &first)?;
+            self.queued = None;
+            self.pending = Some(second);
+            self.last_write = Some(now);
+        } else if let Some(second) = self.pending {
+            bus.i2c
+                .write(bus::WuKongBus::<TWIM, L>::I2C_ADDR, &second)?;
+            self.pending = None;
+            self.last_write = Some(now);
         }
         Ok(())
     }
@@ -0,0 +1,252 @@
+/*!
+Software effects for the mood lights. The Wukong firmware
+can only breathe at one speed or hold a fixed intensity;
+the effects here are built from timed
+[MoodLights::Intensity] updates sent through a
+[MoodLightController], so they run without blocking.
+
+Each intensity update takes two I2C writes separated by
+[MoodLightController::GAP], and the next update must wait
+another gap. The engine never starts an update before the
+previous one has finished, so effect timing finer than a
+couple of hundred milliseconds is approximated by whatever
+intensity is current when the bus is next free.
+*/
+
+use super::{Error, MoodLightController, MoodLights};
+use crate::bus;
+
+use nrf52833_hal::twim;
+
+/// A mood light effect. Intensities are 0..=100 and times
+/// are in milliseconds.
+#[derive(Debug, Clone, Copy)]
+pub enum Effect<'a> {
+    /// Fade linearly from intensity `from` to intensity `to`
+    /// over `duration`, then hold `to`. A zero duration sets
+    /// `to` at once.
+    Fade { from: u8, to: u8, duration: u32 },
+    /// Blink `count` times at `intensity`, on for half of
+    /// each `period` and off for the other half, then stay
+    /// off. The period must be at least 2.
+    Blink {
+        count: u16,
+        intensity: u8,
+        period: u32,
+    },
+    /// Double-pulse heartbeat at `intensity`, once per
+    /// `period`, forever. The period must be at least 10.
+    Heartbeat { intensity: u8, period: u32 },
+    /// Morse code SOS at `intensity` with a dot length of
+    /// `unit`, forever.
+    Sos { intensity: u8, unit: u32 },
+    /// Step through the intensities in `table`, holding
+    /// each for `step`. When the table is done, either start
+    /// over (`repeat`) or hold the last intensity.
+    Waveform {
+        table: &'a [u8],
+        step: u32,
+        repeat: bool,
+    },
+}
+
+/// SOS as (on, length in units) pairs, including the gap
+/// before the message repeats.
+const SOS: [(bool, u32); 18] = [
+    (true, 1),
+    (false, 1),
+    (true, 1),
+    (false, 1),
+    (true, 1),
+    (false, 3),
+    (true, 3),
+    (false, 1),
+    (true, 3),
+    (false, 1),
+    (true, 3),
+    (false, 3),
+    (true, 1),
+    (false, 1),
+    (true, 1),
+    (false, 1),
+    (true, 1),
+    (false, 7),
+];
+
+impl Effect<'_> {
+    /// Check that the effect's parameters are in range.
+    fn validate(&self) -> Result<(), Error> {
+        let check = |intensity: u8| {
+            if intensity > 100 {
+                return Err(Error::Overintensity(intensity));
+            }
+            Ok(())
+        };
+        match *self {
+            Effect::Fade { from, to, .. } => {
+                check(from)?;
+                check(to)?;
+            }
+            Effect::Blink {
+                intensity, period, ..
+            } => {
+                check(intensity)?;
+                // Shorter periods would never be on.
+                if period < 2 {
+                    return Err(Error::InvalidEffect);
+                }
+            }
+            Effect::Heartbeat { intensity, period } => {
+                check(intensity)?;
+                // Shorter periods could not show both beats.
+                if period < 10 {
+                    return Err(Error::InvalidEffect);
+                }
+            }
+            Effect::Sos { intensity, unit } => {
+                check(intensity)?;
+                if unit == 0 {
+                    return Err(Error::InvalidEffect);
+                }
+            }
+            Effect::Waveform { table, step, .. } => {
+                if table.is_empty() || step == 0 {
+                    return Err(Error::InvalidEffect);
+                }
+                for &intensity in table {
+                    check(intensity)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Intensity `elapsed` milliseconds into the effect, and
+    /// whether the effect has finished.
+    fn intensity(&self, elapsed: u32) -> (u8, bool) {
+        match *self {
+            Effect::Fade { from, to, duration } => {
+                if elapsed >= duration {
+                    return (to, true);
+                }
+                let delta = (to as i64 - from as i64) * elapsed as i64 / duration as i64;
+                ((from as i64 + delta) as u8, false)
+            }
+            Effect::Blink {
+                count,
+                intensity,
+                period,
+            } => {
+                if elapsed / period >= count as u32 {
+                    return (0, true);
+                }
+                let on = elapsed % period < period / 2;
+                (if on { intensity } else { 0 }, false)
+            }
+            Effect::Heartbeat { intensity, period } => {
+                // Beat in the first and third tenths of the period.
+                let tenth = (elapsed % period) as u64 * 10 / period as u64;
+                let on = tenth == 0 || tenth == 2;
+                (if on { intensity } else { 0 }, false)
+            }
+            Effect::Sos { intensity, unit } => {
+                let total: u32 = SOS.iter().map(|&(_, units)| units).sum();
+                let mut t = (elapsed / unit) % total;
+                for (on, units) in SOS {
+                    if t < units {
+                        return (if on { intensity } else { 0 }, false);
+                    }
+                    t -= units;
+                }
+                unreachable!()
+            }
+            Effect::Waveform {
+                table,
+                step,
+                repeat,
+            } => {
+                let index = (elapsed / step) as usize;
+                if repeat {
+                    (table[index % table.len()], false)
+                } else if index >= table.len() {
+                    (table[table.len() - 1], true)
+                } else {
+                    (table[index], false)
+                }
+            }
+        }
+    }
+}
+
+/// Non-blocking runner for a mood light [Effect].
+#[derive(Debug, Clone)]
+pub struct EffectEngine<'a> {
+    effect: Effect<'a>,
+    start: u32,
+    sent: Option<u8>,
+    finished: bool,
+}
+
+impl<'a> EffectEngine<'a> {
+    /// Start running `effect` at time `now`.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if an intensity in the effect is
+    ///   out of range.
+    /// * Returns an error if an SOS unit or a waveform step
+    ///   is zero, or a waveform table is empty.
+    /// * Returns an error if a blink period is less than 2
+    ///   or a heartbeat period is less than 10.
+    pub fn new(effect: Effect<'a>, now: u32) -> Result<Self, Error> {
+        effect.validate()?;
+        Ok(Self {
+            effect,
+            start: now,
+            sent: None,
+            finished: false,
+        })
+    }
+
+    /// The effect being run.
+    pub fn effect(&self) -> &Effect<'a> {
+        &self.effect
+    }
+
+    /// True once a finite effect has sent its final
+    /// intensity.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Advance the effect to time `now`, sending intensity
+    /// changes through `controller` as the protocol allows.
+    /// Call this regularly; it does not block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an I2C write fails.
+    pub fn update<TWIM, L>(
+        &mut self,
+        controller: &mut MoodLightController,
+        bus: &mut bus::WuKongBus<TWIM, L>,
+        now: u32,
+    ) -> Result<(), bus::Error>
+    where
+        TWIM: twim::Instance,
+    {
+        if !controller.is_settled() {
+            return controller.poll(bus, now);
+        }
+        if self.finished {
+            return Ok(());
+        }
+        let (intensity, done) = self.effect.intensity(now.wrapping_sub(self.start));
+        if self.sent != Some(intensity) {
+            controller.set(bus, MoodLights::Intensity(intensity), now)?;
+            self.sent = Some(intensity);
+        }
+        self.finished = done;
+        Ok(())
+    }
+}
@@ -35,8 +35,11 @@ impl Motor {
     /// # Errors
     ///
     /// Returns an error when given an out-of-range ID.
-    pub fn new(motor: u8) -> Result<Self, Error> {
-        motor.try_into()
+    pub const fn new(motor: u8) -> Result<Self, Error> {
+        if motor < 1 || motor > 2 {
+            return Err(Error::InvalidIndex(motor));
+        }
+        Ok(Motor(motor - 1))
     }
 }
 
@@ -50,14 +53,11 @@ impl core::convert::TryFrom<u8> for Motor {
     type Error = Error;
 
     fn try_from(motor: u8) -> Result<Self, Error> {
-        if !(1..=2).contains(&motor) {
-            return Err(Error::InvalidIndex(motor));
-        }
-        Ok(Motor(motor - 1))
+        Self::new(motor)
     }
 }
 
-impl<TWIM> bus::WuKongBus<TWIM>
+impl<TWIM, L> bus::WuKongBus<TWIM, L>
 where
     TWIM: twim::Instance,
 {
@@ -78,3 +78,20 @@ where
         Ok(())
     }
 }
+
+/// Make a [Motor] from a one-based motor number, checked at
+/// compile time.
+///
+/// ```ignore
+/// static LEFT: Motor = motor!(1);
+/// ```
+#[macro_export]
+macro_rules! motor {
+    ($motor:expr) => {{
+        const MOTOR: $crate::bus::Motor = match $crate::bus::Motor::new($motor) {
+            Ok(motor) => motor,
+            Err(_) => panic!("motor number out of range (1..=2)"),
+        };
+        MOTOR
+    }};
+}
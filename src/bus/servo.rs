@@ -3,10 +3,55 @@ Driver for Wukong servos. The Wukong has eight SVG servo
 ports are controlled via the I2C bus. The interface here is
 "inspired by" the MicroPython and PXT implementations, but
 is a bit more flexible.
+
+Servo ids, angles and configurations can be built in `const`
+context, so that a robot's servo layout can be described by
+`static` or `const` items checked at build time: see the
+[servo!](crate::servo!), [servo_angle!](crate::servo_angle!),
+[servo_config!](crate::servo_config!),
+[configured_servo!](crate::configured_servo!) and
+[servo_group!](crate::servo_group!) macros.
+
+A robot's [ServoConfig] is tied to a type implementing
+[ServoLayout], which is installed on the bus with
+[WuKongBus::with_servo_layout](bus::WuKongBus::with_servo_layout).
+Servos are commanded through [ConfiguredServo] handles for
+that layout, so the bus can only be handed servos from its
+own configuration, and an unconfigured servo port can never
+be addressed:
+
+```ignore
+#[derive(Debug, Clone, Copy)]
+struct Arm;
+
+impl ServoLayout for Arm {
+    const CONFIG: ServoConfig = servo_config![1 => 180, 2 => 270];
+}
+
+const SHOULDER: ConfiguredServo<Arm> = configured_servo!(Arm, 1);
+
+let mut wkb = WuKongBus::new(twim, scl, sda).with_servo_layout::<Arm>();
+wkb.set_servo_angle(SHOULDER, servo_angle!(90))?;
+```
+
+Servos linked into a group are commanded together through
+a [ServoGroup] handle, and only that way: see
+[WuKongBus::set_servo_group_angle](bus::WuKongBus::set_servo_group_angle).
+
+The bus keeps track of which servos are "active": that is,
+have been commanded to an angle since they were last marked
+idle. The Wukong firmware has no command to release a servo,
+so marking a servo idle does not stop it: see
+[WuKongBus::mark_servo_idle](bus::WuKongBus::mark_servo_idle).
 */
 
+pub mod recording;
+
 use crate::bus;
 
+use core::marker::PhantomData;
+
+use libm::roundf;
 use nrf52833_hal::twim;
 
 /// Error during servo operation.
@@ -16,6 +61,11 @@ pub enum Error {
     InvalidIndex(u8),
     /// Given servo angle out of range.
     InvalidAngle(u16),
+    /// Given servo angle in millidegrees out of range.
+    InvalidMillidegrees(u32),
+    /// Given floating-point servo angle out of range or not
+    /// a number.
+    InvalidAngleF32(f32),
     /// Given servo is a repeat in initialization.
     RepeatServo(Servo),
     /// Given servo is accessed while unconfigured.
@@ -23,6 +73,19 @@ pub enum Error {
     /// Attempted to drive given servo to the given angle,
     /// past its given max angle.
     Overangle(Servo, ServoAngle, ServoAngle),
+    /// Recording has no room for more frames.
+    RecordingFull(usize),
+    /// Serialized recording is malformed.
+    BadRecording,
+    /// Given playback speed (percent) is invalid.
+    InvalidSpeed(u16),
+    /// Given servo cannot be linked as requested: it is
+    /// already in a group, or would link to itself. Also
+    /// given for a linked servo commanded outside its group.
+    BadLink(Servo),
+    /// Given group follower would be driven outside its
+    /// range.
+    LinkRange(Servo),
 }
 
 impl From<Error> for bus::Error {
@@ -31,26 +94,92 @@ impl From<Error> for bus::Error {
     }
 }
 
-
-/// Servo angle.
+/// Servo angle. Angles are held in fixed point with a
+/// resolution of a thousandth of a degree, and must be less
+/// than 360°.
 #[derive(Debug, Clone, Copy)]
-pub struct ServoAngle(u16);
+pub struct ServoAngle(u32);
 
 impl ServoAngle {
+    /// Millidegrees in a full circle.
+    const FULL_CIRCLE: u32 = 360_000;
+
     /// Make a new servo angle.
     ///
     /// # Errors
     ///
     /// Returns an error if `angle` is not a valid angle in
     /// degrees (0..=359).
-    pub fn new(angle: u16) -> Result<Self, Error> {
-        angle.try_into()
+    pub const fn new(angle: u16) -> Result<Self, Error> {
+        if angle >= 360 {
+            return Err(Error::InvalidAngle(angle));
+        }
+        Ok(ServoAngle(angle as u32 * 1000))
+    }
+
+    /// Make a new servo angle from a fixed-point angle in
+    /// thousandths of a degree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `angle` is not a valid angle in
+    /// millidegrees (0..360_000).
+    pub const fn from_millidegrees(angle: u32) -> Result<Self, Error> {
+        if angle >= Self::FULL_CIRCLE {
+            return Err(Error::InvalidMillidegrees(angle));
+        }
+        Ok(ServoAngle(angle))
+    }
+
+    /// Make a new servo angle from a floating-point angle in
+    /// degrees, rounded to the nearest millidegree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `angle` is not a number or is not
+    /// a valid angle in degrees (0.0..360.0).
+    pub fn from_degrees_f32(angle: f32) -> Result<Self, Error> {
+        let millidegrees = roundf(angle * 1000.0);
+        if !(0.0..Self::FULL_CIRCLE as f32).contains(&millidegrees) {
+            return Err(Error::InvalidAngleF32(angle));
+        }
+        Ok(ServoAngle(millidegrees as u32))
+    }
+
+    /// Make a new servo angle from a floating-point angle in
+    /// radians, rounded to the nearest millidegree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `angle` is not a number or is not
+    /// a valid angle in radians (0.0..2π).
+    pub fn from_radians(angle: f32) -> Result<Self, Error> {
+        Self::from_degrees_f32(angle.to_degrees()).map_err(|_| Error::InvalidAngleF32(angle))
+    }
+
+    /// This angle in thousandths of a degree.
+    pub const fn millidegrees(self) -> u32 {
+        self.0
+    }
+
+    /// This angle in degrees.
+    pub fn degrees_f32(self) -> f32 {
+        self.0 as f32 / 1000.0
+    }
+
+    /// This angle in radians.
+    pub fn radians(self) -> f32 {
+        self.degrees_f32().to_radians()
     }
 }
 
+/// Convert to whole degrees, truncating any fraction of a
+/// degree, so that the result is always a valid
+/// [ServoAngle::new] angle. (Only the scaling of an angle for
+/// the Wukong firmware rounds.)
 impl From<ServoAngle> for u16 {
     fn from(angle: ServoAngle) -> Self {
-        angle.0
+        (angle.0 / 1000) as u16
     }
 }
 
@@ -58,10 +187,7 @@ impl core::convert::TryFrom<u16> for ServoAngle {
     type Error = Error;
 
     fn try_from(angle: u16) -> Result<Self, Error> {
-        if angle >= 360 {
-            return Err(Error::InvalidAngle(angle));
-        }
-        Ok(ServoAngle(angle))
+        Self::new(angle)
     }
 }
 
@@ -76,8 +202,11 @@ impl Servo {
     /// # Errors
     ///
     /// Returns an error when given an out-of-range ID.
-    pub fn new(servo: u8) -> Result<Self, Error> {
-        servo.try_into()
+    pub const fn new(servo: u8) -> Result<Self, Error> {
+        if servo < 1 || servo > 8 {
+            return Err(Error::InvalidIndex(servo));
+        }
+        Ok(Servo(servo - 1))
     }
 }
 
@@ -91,30 +220,60 @@ impl core::convert::TryFrom<u8> for Servo {
     type Error = Error;
 
     fn try_from(servo: u8) -> Result<Self, Error> {
-        if !(1..=8).contains(&servo) {
-            return Err(Error::InvalidIndex(servo));
-        }
-        Ok(Servo(servo - 1))
+        Self::new(servo)
     }
 }
 
 type ServoMaxAngles = [Option<ServoAngle>; 8];
+type ServoLinks = [Option<(Servo, ServoLink)>; 8];
+
+/// How a grouped servo follows its group leader. Offsets
+/// are in millidegrees.
+#[derive(Debug, Clone, Copy)]
+pub enum ServoLink {
+    /// Follow the leader's angle plus the given offset.
+    Offset(i32),
+    /// Mirror the leader, as for a servo mounted facing it:
+    /// the follower is driven to its max angle less the
+    /// leader's angle, plus the given offset.
+    Mirror(i32),
+}
 
 /// Configuration information for servos includes
-/// per-servo enablement and max angles.
+/// per-servo enablement and max angles, and servo groups.
+///
+/// A configuration is put to use as the `CONFIG` of a
+/// [ServoLayout]. Servos are then commanded through
+/// [ConfiguredServo] handles, and groups through
+/// [ServoGroup] handles, for that layout.
 #[derive(Debug, Clone)]
 pub struct ServoConfig {
     servo_max_angles: ServoMaxAngles,
+    servo_links: ServoLinks,
 }
 
 impl ServoConfig {
+    /// Configuration with no servos.
+    const EMPTY: ServoConfig = ServoConfig {
+        servo_max_angles: [None; 8],
+        servo_links: [None; 8],
+    };
+
     /// Make a new servo config from an iterator over servos
     /// and their max angles.
     ///
+    /// A config made at run time cannot be used to command
+    /// servos: only the `const` config of a [ServoLayout] can.
+    /// Use [ServoConfig::from_slice] or
+    /// [servo_config!](crate::servo_config!) instead.
+    ///
     /// # Errors
     ///
     /// * Returns an error if a servo is repeated in the iterator.
     /// * Returns an error if a max angle is 0°.
+    #[deprecated(
+        note = "a run-time config cannot be installed; use `ServoConfig::from_slice` or `servo_config!` as a `ServoLayout::CONFIG`"
+    )]
     pub fn new<C, I>(config: C) -> Result<Self, bus::Error>
     where
         C: IntoIterator<Item = I>,
@@ -124,54 +283,564 @@ impl ServoConfig {
         for item in config.into_iter() {
             let (servo, servo_angle) = item.into();
             let servo_value = u8::from(servo) as usize;
-            let servo_angle_value = u16::from(servo_angle);
-            if servo_angle_value < 1 {
-                return Err(Error::InvalidAngle(servo_angle_value).into());
+            if servo_angle.0 == 0 {
+                return Err(Error::InvalidAngle(0).into());
             }
             if servo_max_angles[servo_value].is_some() {
                 return Err(Error::RepeatServo(servo).into());
             }
             servo_max_angles[servo_value] = Some(servo_angle);
         }
-        Ok(Self { servo_max_angles })
+        Ok(Self {
+            servo_max_angles,
+            servo_links: [None; 8],
+        })
+    }
+
+    /// Make a new servo config from a slice of servos and
+    /// their max angles. This is usable in `const` context:
+    /// see also [servo_config!](crate::servo_config!).
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if a servo is repeated in the slice.
+    /// * Returns an error if a max angle is 0°.
+    pub const fn from_slice(config: &[(Servo, ServoAngle)]) -> Result<Self, Error> {
+        let mut servo_max_angles: ServoMaxAngles = [None; 8];
+        let mut i = 0;
+        while i < config.len() {
+            let (servo, servo_angle) = config[i];
+            if servo_angle.0 == 0 {
+                return Err(Error::InvalidAngle(0));
+            }
+            if servo_max_angles[servo.0 as usize].is_some() {
+                return Err(Error::RepeatServo(servo));
+            }
+            servo_max_angles[servo.0 as usize] = Some(servo_angle);
+            i += 1;
+        }
+        Ok(Self {
+            servo_max_angles,
+            servo_links: [None; 8],
+        })
+    }
+
+    /// The max angle of the given `servo`, or `None` if it
+    /// is not configured.
+    pub const fn max_angle(&self, servo: Servo) -> Option<ServoAngle> {
+        self.servo_max_angles[servo.0 as usize]
+    }
+
+    /// True if the given `servo` is linked to another: that
+    /// is, it follows a servo or leads a group with
+    /// followers.
+    pub const fn is_linked(&self, servo: Servo) -> bool {
+        if self.servo_links[servo.0 as usize].is_some() {
+            return true;
+        }
+        let mut i = 0;
+        while i < self.servo_links.len() {
+            if let Some((leader, _)) = self.servo_links[i] {
+                if leader.0 == servo.0 {
+                    return true;
+                }
+            }
+            i += 1;
+        }
+        false
+    }
+
+    /// Add the configured `follower` servo to the group led
+    /// by the configured `leader` servo, following it as
+    /// given by `link`. A leader may have several
+    /// followers, but groups do not nest. This is usable in
+    /// `const` context.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if either servo is not configured.
+    /// * Returns an error if `follower` is `leader`, already
+    ///   follows a servo, or leads a group.
+    /// * Returns an error if `leader` follows a servo.
+    pub const fn link(
+        mut self,
+        leader: Servo,
+        follower: Servo,
+        link: ServoLink,
+    ) -> Result<Self, Error> {
+        if self.servo_max_angles[leader.0 as usize].is_none() {
+            return Err(Error::UnconfiguredServo(leader));
+        }
+        if self.servo_max_angles[follower.0 as usize].is_none() {
+            return Err(Error::UnconfiguredServo(follower));
+        }
+        if leader.0 == follower.0 || self.servo_links[follower.0 as usize].is_some() {
+            return Err(Error::BadLink(follower));
+        }
+        if self.servo_links[leader.0 as usize].is_some() {
+            return Err(Error::BadLink(leader));
+        }
+        let mut i = 0;
+        while i < self.servo_links.len() {
+            if let Some((l, _)) = self.servo_links[i] {
+                if l.0 == follower.0 {
+                    return Err(Error::BadLink(follower));
+                }
+            }
+            i += 1;
+        }
+        self.servo_links[follower.0 as usize] = Some((leader, link));
+        Ok(self)
+    }
+}
+
+/// A robot's servo layout: a type standing for the one
+/// [ServoConfig] given as its `CONFIG`. Servo handles and
+/// the bus are tied to a layout, so servos from different
+/// configurations cannot be mixed.
+pub trait ServoLayout: Copy + core::fmt::Debug {
+    /// The servo configuration.
+    const CONFIG: ServoConfig;
+}
+
+impl ServoLayout for bus::NoServos {
+    const CONFIG: ServoConfig = ServoConfig::EMPTY;
+}
+
+/// Handle for a servo configured in the [ServoLayout] `L`.
+/// The handle carries the servo's configuration with it.
+#[derive(Debug, Clone, Copy)]
+pub struct ConfiguredServo<L> {
+    servo: Servo,
+    max_angle: ServoAngle,
+    layout: PhantomData<L>,
+}
+
+impl<L: ServoLayout> ConfiguredServo<L> {
+    /// Get the handle for the given `servo` in layout `L`.
+    /// This is usable in `const` context: see also
+    /// [configured_servo!](crate::configured_servo!).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the given servo is not configured.
+    pub const fn new(servo: Servo) -> Result<Self, Error> {
+        match L::CONFIG.max_angle(servo) {
+            Some(max_angle) => Ok(ConfiguredServo {
+                servo,
+                max_angle,
+                layout: PhantomData,
+            }),
+            None => Err(Error::UnconfiguredServo(servo)),
+        }
+    }
+
+    /// Iterate over handles for all servos configured in
+    /// layout `L`, in servo order.
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..8).filter_map(|index| Self::new(Servo(index)).ok())
+    }
+}
+
+impl<L> ConfiguredServo<L> {
+    /// The servo this handle controls.
+    pub const fn servo(&self) -> Servo {
+        self.servo
+    }
+
+    /// The configured max angle of this servo.
+    pub const fn max_angle(&self) -> ServoAngle {
+        self.max_angle
+    }
+}
+
+impl<L> From<ConfiguredServo<L>> for Servo {
+    fn from(servo: ConfiguredServo<L>) -> Self {
+        servo.servo
+    }
+}
+
+type GroupTargets<L> = [Option<(ConfiguredServo<L>, ServoAngle)>; 8];
+
+/// Handle for a group of servos configured in the
+/// [ServoLayout] `L`: a leader and the servos that follow
+/// it.
+#[derive(Debug, Clone, Copy)]
+pub struct ServoGroup<L> {
+    leader: ConfiguredServo<L>,
+    followers: [Option<(ConfiguredServo<L>, ServoLink)>; 7],
+}
+
+impl<L: ServoLayout> ServoGroup<L> {
+    /// Get the handle for the group led by the given
+    /// `leader` servo in layout `L`. A servo with no
+    /// followers leads a group of one. This is usable in
+    /// `const` context: see also
+    /// [servo_group!](crate::servo_group!).
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if `leader` is not configured.
+    /// * Returns an error if `leader` follows another servo.
+    pub const fn new(leader: Servo) -> Result<Self, Error> {
+        let leader = match ConfiguredServo::new(leader) {
+            Ok(leader) => leader,
+            Err(e) => return Err(e),
+        };
+        let links = &L::CONFIG.servo_links;
+        if links[leader.servo.0 as usize].is_some() {
+            return Err(Error::BadLink(leader.servo));
+        }
+        let mut followers = [None; 7];
+        let mut count = 0;
+        let mut i = 0;
+        while i < links.len() {
+            if let Some((l, link)) = links[i] {
+                if l.0 == leader.servo.0 {
+                    // Every linked servo is configured, and a
+                    // leader has at most seven followers.
+                    let follower = match ConfiguredServo::new(Servo(i as u8)) {
+                        Ok(follower) => follower,
+                        Err(e) => return Err(e),
+                    };
+                    followers[count] = Some((follower, link));
+                    count += 1;
+                }
+            }
+            i += 1;
+        }
+        Ok(ServoGroup { leader, followers })
+    }
+}
+
+impl<L: Copy> ServoGroup<L> {
+    /// The leader of this group.
+    pub fn leader(&self) -> ConfiguredServo<L> {
+        self.leader
+    }
+
+    /// Iterate over the followers in this group and how they
+    /// follow the leader.
+    pub fn followers(&self) -> impl Iterator<Item = (ConfiguredServo<L>, ServoLink)> + '_ {
+        self.followers.iter().flatten().copied()
+    }
+
+    /// Find the angle of every servo in the group when the
+    /// leader is at `angle`.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if `angle` is beyond the leader's
+    ///   max angle.
+    /// * Returns an error if a follower would be driven
+    ///   outside its range.
+    pub fn targets(&self, angle: ServoAngle) -> Result<GroupTargets<L>, Error> {
+        let leader = self.leader;
+        if angle.0 > leader.max_angle.0 {
+            return Err(Error::Overangle(leader.servo, angle, leader.max_angle));
+        }
+        let mut targets = [None; 8];
+        targets[0] = Some((leader, angle));
+        for (target, (follower, link)) in targets[1..].iter_mut().zip(self.followers()) {
+            let max = follower.max_angle.0 as i64;
+            let follower_angle = match link {
+                ServoLink::Offset(offset) => angle.0 as i64 + offset as i64,
+                ServoLink::Mirror(offset) => max - angle.0 as i64 + offset as i64,
+            };
+            if !(0..=max).contains(&follower_angle) {
+                return Err(Error::LinkRange(follower.servo));
+            }
+            *target = Some((follower, ServoAngle(follower_angle as u32)));
+        }
+        Ok(targets)
+    }
+}
+
+/// A set of targets for `N` servos configured in the
+/// [ServoLayout] `L`, to be commanded together. Every
+/// target is checked against its servo's max angle when the
+/// pose is made, so a pose can always be commanded.
+#[derive(Debug, Clone, Copy)]
+pub struct Pose<L, const N: usize> {
+    targets: [(ConfiguredServo<L>, ServoAngle); N],
+}
+
+impl<L, const N: usize> Pose<L, N> {
+    /// Make a new pose from servos and their target angles.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a target is beyond its servo's max
+    /// angle.
+    pub fn new(targets: [(ConfiguredServo<L>, ServoAngle); N]) -> Result<Self, Error> {
+        for (servo, angle) in &targets {
+            if angle.0 > servo.max_angle.0 {
+                return Err(Error::Overangle(servo.servo, *angle, servo.max_angle));
+            }
+        }
+        Ok(Self { targets })
+    }
+
+    /// Make a new pose from targets already checked against
+    /// their servos' max angles.
+    #[cfg(feature = "kinematics")]
+    pub(crate) fn from_checked(targets: [(ConfiguredServo<L>, ServoAngle); N]) -> Self {
+        Self { targets }
+    }
+
+    /// Make a new pose from servos and their target angles,
+    /// clamping each target to its servo's max angle.
+    pub fn clamped(mut targets: [(ConfiguredServo<L>, ServoAngle); N]) -> Self {
+        for (servo, angle) in &mut targets {
+            angle.0 = angle.0.min(servo.max_angle.0);
+        }
+        Self { targets }
+    }
+
+    /// The servos and target angles of this pose.
+    pub fn targets(&self) -> &[(ConfiguredServo<L>, ServoAngle); N] {
+        &self.targets
     }
 }
 
-impl<TWIM> bus::WuKongBus<TWIM>
+impl<TWIM> bus::WuKongBus<TWIM> {
+    /// Install the servo layout `L` on this bus. Servos can
+    /// then be commanded through handles for `L`.
+    pub fn with_servo_layout<L: ServoLayout>(self) -> bus::WuKongBus<TWIM, L> {
+        bus::WuKongBus {
+            i2c: self.i2c,
+            active_servos: self.active_servos,
+            servo_layout: PhantomData,
+        }
+    }
+}
+
+impl<TWIM, L> bus::WuKongBus<TWIM, L>
 where
     TWIM: twim::Instance,
+    L: ServoLayout,
 {
-    /// Set the given `servo` to the `given` angle,
-    /// taking into account the given `config`.
+    /// Set the given configured `servo` to the given
+    /// `angle`. The angle is scaled to the servo's max angle
+    /// and rounded to the nearest step the Wukong firmware
+    /// supports: 1/180 of the max angle.
+    ///
+    /// Servos linked into a group can only be commanded
+    /// through [Self::set_servo_group_angle], so that the
+    /// group stays together.
     ///
     /// # Errors
     ///
-    /// * Returns an error if the given servo is not configured.
+    /// * Returns an error if the given servo is linked into a
+    ///   group.
     /// * Returns an error on an attempt to drive the given servo
     ///   beyond its configured max angle.
     /// * Returns an error if the I2C write fails.
     pub fn set_servo_angle(
         &mut self,
-        config: &ServoConfig,
-        servo: Servo,
+        servo: ConfiguredServo<L>,
         angle: ServoAngle,
     ) -> Result<(), bus::Error> {
-        let servo_value = u8::from(servo);
-        let opt_max_angle = config.servo_max_angles[servo_value as usize];
-        let max_angle = opt_max_angle.ok_or_else(
-            || <bus::Error>::from(Error::UnconfiguredServo(servo))
-        )?;
-        let max_angle_value = u16::from(max_angle);
-        let angle_value = u16::from(angle);
+        if L::CONFIG.is_linked(servo.servo) {
+            return Err(Error::BadLink(servo.servo).into());
+        }
+        self.write_servo_angle(servo, angle)
+    }
+
+    /// Set the given configured `servo` to the given
+    /// `angle`, whether or not it is linked.
+    fn write_servo_angle(
+        &mut self,
+        servo: ConfiguredServo<L>,
+        angle: ServoAngle,
+    ) -> Result<(), bus::Error> {
+        let ConfiguredServo {
+            servo, max_angle, ..
+        } = servo;
+        let max_angle_value = max_angle.0;
+        let angle_value = angle.0;
         if angle_value > max_angle_value {
             return Err(Error::Overangle(servo, angle, max_angle).into());
         }
-        let scaled_angle = angle_value * 180 / max_angle_value;
+        let scaled_angle = (angle_value * 180 + max_angle_value / 2) / max_angle_value;
         assert!(scaled_angle <= 180);
-        let servo_value = servo_value + 3;
+        let servo_value = u8::from(servo) + 3;
 
         let buf = [servo_value, scaled_angle as u8, 0, 0];
         self.i2c.write(Self::I2C_ADDR, &buf)?;
+        self.active_servos |= 1 << u8::from(servo);
+        Ok(())
+    }
+
+    /// Drive every servo in `pose` to its target angle, in
+    /// order. As with [Self::set_servo_angle], servos linked
+    /// into a group cannot be commanded this way.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if a servo in the pose is linked
+    ///   into a group. No servo will have been commanded.
+    /// * Returns an error if an I2C write fails. Servos earlier
+    ///   in the pose will already have been commanded.
+    pub fn set_servo_pose<const N: usize>(&mut self, pose: &Pose<L, N>) -> Result<(), bus::Error> {
+        for (servo, _) in pose.targets() {
+            if L::CONFIG.is_linked(servo.servo) {
+                return Err(Error::BadLink(servo.servo).into());
+            }
+        }
+        for &(servo, angle) in pose.targets() {
+            self.write_servo_angle(servo, angle)?;
+        }
+        Ok(())
+    }
+
+    /// Set the leader of the given servo `group` to the
+    /// given `angle`, and every follower to match. All
+    /// angles are checked before any servo is commanded.
+    ///
+    /// This is the only way to command servos linked into a
+    /// group.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if `angle` is beyond the leader's
+    ///   max angle.
+    /// * Returns an error if a follower would be driven
+    ///   outside its range.
+    /// * Returns an error if an I2C write fails.
+    pub fn set_servo_group_angle(
+        &mut self,
+        group: &ServoGroup<L>,
+        angle: ServoAngle,
+    ) -> Result<(), bus::Error> {
+        for (servo, angle) in group.targets(angle)?.into_iter().flatten() {
+            self.write_servo_angle(servo, angle)?;
+        }
         Ok(())
     }
+
+    /// Mark the given `servo` as idle, so that it no longer
+    /// shows as active.
+    ///
+    /// This does not stop the servo. The Wukong firmware
+    /// provides no command to stop the pulse train to a
+    /// servo port: once commanded, a servo will keep
+    /// holding its last angle (and drawing current) until
+    /// the Wukong is powered off. Marking servos idle only
+    /// lets code sharing the bus keep track of which servos
+    /// are in use. Power down the Wukong or disconnect the
+    /// servo to actually stop it.
+    pub fn mark_servo_idle(&mut self, servo: ConfiguredServo<L>) {
+        self.active_servos &= !(1 << servo.servo.0);
+    }
+
+    /// Mark all servos as idle. Like
+    /// [Self::mark_servo_idle], this does not stop them.
+    pub fn mark_all_servos_idle(&mut self) {
+        self.active_servos = 0;
+    }
+
+    /// True if the given `servo` has been commanded since
+    /// it was last marked idle.
+    pub fn is_servo_active(&self, servo: ConfiguredServo<L>) -> bool {
+        self.active_servos & (1 << servo.servo.0) != 0
+    }
+
+    /// Iterate over the servos that have been commanded
+    /// since they were last marked idle, in servo order.
+    pub fn active_servos(&self) -> impl Iterator<Item = ConfiguredServo<L>> {
+        let active_servos = self.active_servos;
+        ConfiguredServo::all().filter(move |servo| active_servos & (1 << servo.servo.0) != 0)
+    }
+}
+
+/// Make a [Servo] from a one-based servo number, checked at
+/// compile time.
+///
+/// ```ignore
+/// static GRIPPER: Servo = servo!(3);
+/// ```
+#[macro_export]
+macro_rules! servo {
+    ($servo:expr) => {{
+        const SERVO: $crate::bus::Servo = match $crate::bus::Servo::new($servo) {
+            Ok(servo) => servo,
+            Err(_) => panic!("servo number out of range (1..=8)"),
+        };
+        SERVO
+    }};
+}
+
+/// Make a [ServoAngle] from an angle in degrees, checked at
+/// compile time.
+///
+/// ```ignore
+/// static HOME: ServoAngle = servo_angle!(90);
+/// ```
+#[macro_export]
+macro_rules! servo_angle {
+    ($angle:expr) => {{
+        const ANGLE: $crate::bus::ServoAngle = match $crate::bus::ServoAngle::new($angle) {
+            Ok(angle) => angle,
+            Err(_) => panic!("servo angle out of range (0..=359)"),
+        };
+        ANGLE
+    }};
+}
+
+/// Make a [ServoConfig] from servo numbers and max angles
+/// in degrees, checked at compile time.
+///
+/// ```ignore
+/// const CONFIG: ServoConfig = servo_config![1 => 180, 2 => 270];
+/// ```
+#[macro_export]
+macro_rules! servo_config {
+    ($($servo:expr => $max_angle:expr),* $(,)?) => {{
+        const CONFIG: $crate::bus::ServoConfig = match $crate::bus::ServoConfig::from_slice(&[
+            $(($crate::servo!($servo), $crate::servo_angle!($max_angle))),*
+        ]) {
+            Ok(config) => config,
+            Err($crate::bus::servo::Error::RepeatServo(_)) => panic!("servo repeated in config"),
+            Err(_) => panic!("servo max angle must be at least 1°"),
+        };
+        CONFIG
+    }};
+}
+
+/// Get the [ConfiguredServo] handle for a servo number in a
+/// [ServoLayout], checked at compile time.
+///
+/// ```ignore
+/// static SHOULDER: ConfiguredServo<Arm> = configured_servo!(Arm, 1);
+/// ```
+#[macro_export]
+macro_rules! configured_servo {
+    ($layout:ty, $servo:expr) => {{
+        const SERVO: $crate::bus::ConfiguredServo<$layout> =
+            match $crate::bus::ConfiguredServo::<$layout>::new($crate::servo!($servo)) {
+                Ok(servo) => servo,
+                Err(_) => panic!("servo not configured"),
+            };
+        SERVO
+    }};
+}
+
+/// Get the [ServoGroup] handle for the group led by a servo
+/// number in a [ServoLayout], checked at compile time.
+///
+/// ```ignore
+/// static WHEELS: ServoGroup<Rover> = servo_group!(Rover, 1);
+/// ```
+#[macro_export]
+macro_rules! servo_group {
+    ($layout:ty, $leader:expr) => {{
+        const GROUP: $crate::bus::ServoGroup<$layout> =
+            match $crate::bus::ServoGroup::<$layout>::new($crate::servo!($leader)) {
+                Ok(group) => group,
+                Err($crate::bus::servo::Error::BadLink(_)) => panic!("servo follows another servo"),
+                Err(_) => panic!("servo not configured"),
+            };
+        GROUP
+    }};
 }
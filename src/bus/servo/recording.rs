@@ -0,0 +1,442 @@
+/*!
+Teach-and-replay recording for Wukong servos. A
+[ServoRecorder] wraps [WuKongBus::set_servo_angle] and
+keeps a timestamped [Frame] for each command it issues. The
+resulting [Recording] can be replayed by a [ServoPlayer] at
+a chosen speed, and can be serialized to bytes so that it
+can be saved to and loaded from flash.
+
+Timestamps are in milliseconds. The "current time" `now`
+is supplied by the caller, typically from a free-running
+timer; it is allowed to wrap.
+
+[WuKongBus::set_servo_angle]: crate::bus::WuKongBus::set_servo_angle
+*/
+
+use super::{ConfiguredServo, Error, Servo, ServoAngle, ServoLayout};
+use crate::bus;
+
+use core::marker::PhantomData;
+
+use nrf52833_hal::twim;
+
+/// A single recorded command to a servo in the
+/// [ServoLayout] `L`.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<L> {
+    /// Time of the command in milliseconds, relative to the
+    /// first command of the recording.
+    pub time: u32,
+    /// Servo commanded.
+    pub servo: ConfiguredServo<L>,
+    /// Angle commanded.
+    pub angle: ServoAngle,
+}
+
+impl<L> Frame<L> {
+    /// Size of a serialized frame in bytes.
+    pub const SERIALIZED_LEN: usize = 9;
+
+    const EMPTY: Frame<L> = Frame {
+        time: 0,
+        servo: ConfiguredServo {
+            servo: Servo(0),
+            max_angle: ServoAngle(1000),
+            layout: PhantomData,
+        },
+        angle: ServoAngle(0),
+    };
+}
+
+/// A sequence of up to `N` timestamped commands to servos in
+/// the [ServoLayout] `L`.
+#[derive(Debug, Clone)]
+pub struct Recording<L, const N: usize> {
+    frames: [Frame<L>; N],
+    len: usize,
+}
+
+impl<L, const N: usize> Default for Recording<L, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L, const N: usize> Recording<L, N> {
+    /// Serialization format version.
+    const VERSION: u8 = 1;
+    /// Size of the serialized header in bytes.
+    const HEADER_LEN: usize = 3;
+
+    /// Make a new empty recording. `N` must be at most
+    /// 65535, as the serialized frame count is a `u16`: this
+    /// is checked at compile time.
+    pub const fn new() -> Self {
+        const { assert!(N <= u16::MAX as usize, "recording too long to serialize") };
+        Self {
+            frames: [Frame::EMPTY; N],
+            len: 0,
+        }
+    }
+
+    /// The recorded frames, in time order.
+    pub fn frames(&self) -> &[Frame<L>] {
+        &self.frames[..self.len]
+    }
+
+    /// Number of recorded frames.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// True if nothing has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Discard all recorded frames.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Total duration of the recording in milliseconds.
+    pub fn duration(&self) -> u32 {
+        self.frames().last().map(|f| f.time).unwrap_or(0)
+    }
+
+    /// Append a `frame` to the recording.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if the recording is full.
+    /// * Returns an error if `frame` is earlier than the
+    ///   last recorded frame.
+    /// * Returns an error if the frame's angle is beyond its
+    ///   servo's max angle.
+    pub fn push(&mut self, frame: Frame<L>) -> Result<(), Error> {
+        if self.len >= N {
+            return Err(Error::RecordingFull(N));
+        }
+        if frame.time < self.duration() {
+            return Err(Error::BadRecording);
+        }
+        let ConfiguredServo {
+            servo, max_angle, ..
+        } = frame.servo;
+        if frame.angle.0 > max_angle.0 {
+            return Err(Error::Overangle(servo, frame.angle, max_angle));
+        }
+        self.frames[self.len] = frame;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Number of bytes needed to serialize this recording.
+    pub fn serialized_len(&self) -> usize {
+        Self::HEADER_LEN + self.len * Frame::<L>::SERIALIZED_LEN
+    }
+
+    /// Serialize this recording into `buf`, returning the
+    /// number of bytes written. The format is a version
+    /// byte and a little-endian `u16` frame count, followed
+    /// by each frame as a little-endian `u32` time, a
+    /// zero-based servo index byte and a little-endian
+    /// `u32` angle in millidegrees.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `buf` is too short.
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = self.serialized_len();
+        if buf.len() < len {
+            return Err(Error::BadRecording);
+        }
+        buf[0] = Self::VERSION;
+        buf[1..3].copy_from_slice(&(self.len as u16).to_le_bytes());
+        let chunks = buf[Self::HEADER_LEN..len].chunks_exact_mut(Frame::<L>::SERIALIZED_LEN);
+        for (frame, chunk) in self.frames().iter().zip(chunks) {
+            chunk[0..4].copy_from_slice(&frame.time.to_le_bytes());
+            chunk[4] = u8::from(frame.servo.servo());
+            chunk[5..9].copy_from_slice(&frame.angle.millidegrees().to_le_bytes());
+        }
+        Ok(len)
+    }
+}
+
+impl<L: ServoLayout, const N: usize> Recording<L, N> {
+    /// Deserialize a recording previously written by
+    /// [Recording::to_bytes]. Each servo in the recording
+    /// is looked up in the layout `L`.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if `buf` is malformed or truncated.
+    /// * Returns an error if the recording holds more than
+    ///   `N` frames.
+    /// * Returns an error if a frame names an invalid or
+    ///   unconfigured servo, or an invalid angle or one
+    ///   beyond its servo's max angle.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() < Self::HEADER_LEN || buf[0] != Self::VERSION {
+            return Err(Error::BadRecording);
+        }
+        let frame_len = Frame::<L>::SERIALIZED_LEN;
+        let count = u16::from_le_bytes([buf[1], buf[2]]) as usize;
+        if count > N {
+            return Err(Error::RecordingFull(N));
+        }
+        let len = Self::HEADER_LEN + count * frame_len;
+        if buf.len() < len {
+            return Err(Error::BadRecording);
+        }
+        let mut recording = Self::new();
+        for chunk in buf[Self::HEADER_LEN..len].chunks_exact(frame_len) {
+            let time = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            let servo = ConfiguredServo::new(Servo::new(
+                chunk[4].checked_add(1).ok_or(Error::BadRecording)?,
+            )?)?;
+            let angle = ServoAngle::from_millidegrees(u32::from_le_bytes([
+                chunk[5], chunk[6], chunk[7], chunk[8],
+            ]))?;
+            recording.push(Frame { time, servo, angle })?;
+        }
+        Ok(recording)
+    }
+}
+
+/// Records servo commands as they are issued.
+#[derive(Debug, Clone)]
+pub struct ServoRecorder<L, const N: usize> {
+    recording: Recording<L, N>,
+    start: Option<u32>,
+}
+
+impl<L: ServoLayout, const N: usize> Default for ServoRecorder<L, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L: ServoLayout, const N: usize> ServoRecorder<L, N> {
+    /// Make a new recorder with an empty recording. The
+    /// recording's time base starts with the first command.
+    pub const fn new() -> Self {
+        Self {
+            recording: Recording::new(),
+            start: None,
+        }
+    }
+
+    /// Set the given `servo` to the given `angle` via `bus`
+    /// as with [WuKongBus::set_servo_angle], recording the
+    /// command as happening at time `now`.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if the recording is full. In this
+    ///   case the servo is not commanded.
+    /// * Returns any error from [WuKongBus::set_servo_angle].
+    ///
+    /// [WuKongBus::set_servo_angle]: crate::bus::WuKongBus::set_servo_angle
+    pub fn set_servo_angle<TWIM>(
+        &mut self,
+        bus: &mut bus::WuKongBus<TWIM, L>,
+        servo: ConfiguredServo<L>,
+        angle: ServoAngle,
+        now: u32,
+    ) -> Result<(), bus::Error>
+    where
+        TWIM: twim::Instance,
+    {
+        if self.recording.len() >= N {
+            return Err(Error::RecordingFull(N).into());
+        }
+        bus.set_servo_angle(servo, angle)?;
+        let start = *self.start.get_or_insert(now);
+        let time = now.wrapping_sub(start);
+        self.recording.push(Frame { time, servo, angle })?;
+        Ok(())
+    }
+
+    /// The recording made so far.
+    pub fn recording(&self) -> &Recording<L, N> {
+        &self.recording
+    }
+
+    /// Finish recording, returning the result.
+    pub fn finish(self) -> Recording<L, N> {
+        self.recording
+    }
+}
+
+/// Non-blocking replay of a [Recording].
+#[derive(Debug, Clone)]
+pub struct ServoPlayer<'a, L, const N: usize> {
+    recording: &'a Recording<L, N>,
+    next: usize,
+    start: u32,
+    speed: u16,
+}
+
+impl<'a, L: ServoLayout, const N: usize> ServoPlayer<'a, L, N> {
+    /// Start playing `recording` at time `now`. The
+    /// playback `speed` is a percentage of real time: `100`
+    /// replays as recorded, `200` replays twice as fast.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `speed` is zero.
+    pub fn new(recording: &'a Recording<L, N>, now: u32, speed: u16) -> Result<Self, Error> {
+        if speed == 0 {
+            return Err(Error::InvalidSpeed(speed));
+        }
+        Ok(Self {
+            recording,
+            next: 0,
+            start: now,
+            speed,
+        })
+    }
+
+    /// True once every frame has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.recording.len()
+    }
+
+    /// Issue every frame that has come due by time `now`.
+    /// Call this regularly; it does not block.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from
+    /// [WuKongBus::set_servo_angle]. The failed frame will
+    /// be retried on the next call.
+    ///
+    /// [WuKongBus::set_servo_angle]: crate::bus::WuKongBus::set_servo_angle
+    pub fn update<TWIM>(
+        &mut self,
+        bus: &mut bus::WuKongBus<TWIM, L>,
+        now: u32,
+    ) -> Result<(), bus::Error>
+    where
+        TWIM: twim::Instance,
+    {
+        let elapsed = now.wrapping_sub(self.start) as u64 * self.speed as u64 / 100;
+        while let Some(frame) = self.recording.frames().get(self.next) {
+            if frame.time as u64 > elapsed {
+                break;
+            }
+            bus.set_servo_angle(frame.servo, frame.angle)?;
+            self.next += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::ServoConfig;
+
+    #[derive(Debug, Clone, Copy)]
+    struct Arm;
+
+    impl ServoLayout for Arm {
+        const CONFIG: ServoConfig = crate::servo_config![1 => 180, 2 => 90];
+    }
+
+    const S1: ConfiguredServo<Arm> = crate::configured_servo!(Arm, 1);
+    const S2: ConfiguredServo<Arm> = crate::configured_servo!(Arm, 2);
+
+    fn frame(time: u32, servo: ConfiguredServo<Arm>, millidegrees: u32) -> Frame<Arm> {
+        let angle = ServoAngle::from_millidegrees(millidegrees).unwrap();
+        Frame { time, servo, angle }
+    }
+
+    fn recording() -> Recording<Arm, 4> {
+        let mut recording = Recording::new();
+        recording.push(frame(0, S1, 0)).unwrap();
+        recording.push(frame(20, S2, 45_500)).unwrap();
+        recording.push(frame(1000, S1, 180_000)).unwrap();
+        recording
+    }
+
+    #[test]
+    fn round_trip() {
+        let recording = recording();
+        let mut buf = [0; 64];
+        let len = recording.to_bytes(&mut buf).unwrap();
+        assert_eq!(len, recording.serialized_len());
+        let loaded = Recording::<Arm, 4>::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(loaded.len(), recording.len());
+        for (a, b) in loaded.frames().iter().zip(recording.frames()) {
+            assert_eq!(a.time, b.time);
+            assert_eq!(u8::from(a.servo.servo()), u8::from(b.servo.servo()));
+            assert_eq!(a.angle.millidegrees(), b.angle.millidegrees());
+        }
+        assert!(matches!(
+            Recording::<Arm, 2>::from_bytes(&buf[..len]),
+            Err(Error::RecordingFull(2))
+        ));
+        assert!(matches!(
+            recording.to_bytes(&mut buf[..len - 1]),
+            Err(Error::BadRecording)
+        ));
+    }
+
+    #[test]
+    fn over_max_angle() {
+        let mut recording = recording();
+        assert!(matches!(
+            recording.push(frame(2000, S2, 90_001)),
+            Err(Error::Overangle(..))
+        ));
+        assert_eq!(recording.len(), 3);
+
+        let mut buf = [0; 64];
+        let len = recording.to_bytes(&mut buf).unwrap();
+        // Second frame's angle: 100° on the 90° servo.
+        let angle = 3 + Frame::<Arm>::SERIALIZED_LEN + 5;
+        buf[angle..angle + 4].copy_from_slice(&100_000u32.to_le_bytes());
+        assert!(matches!(
+            Recording::<Arm, 4>::from_bytes(&buf[..len]),
+            Err(Error::Overangle(..))
+        ));
+    }
+
+    #[test]
+    fn bad_bytes() {
+        let recording = recording();
+        let mut buf = [0; 64];
+        let len = recording.to_bytes(&mut buf).unwrap();
+
+        for cut in [0, 2, 3, len - 1] {
+            assert!(matches!(
+                Recording::<Arm, 4>::from_bytes(&buf[..cut]),
+                Err(Error::BadRecording)
+            ));
+        }
+
+        let mut bad = buf;
+        bad[3 + 4] = 0xff;
+        assert!(matches!(
+            Recording::<Arm, 4>::from_bytes(&bad[..len]),
+            Err(Error::BadRecording)
+        ));
+
+        // Servo 3 is not configured.
+        let mut bad = buf;
+        bad[3 + 4] = 2;
+        assert!(matches!(
+            Recording::<Arm, 4>::from_bytes(&bad[..len]),
+            Err(Error::UnconfiguredServo(_))
+        ));
+
+        let mut bad = buf;
+        bad[0] = 0xff;
+        assert!(matches!(
+            Recording::<Arm, 4>::from_bytes(&bad[..len]),
+            Err(Error::BadRecording)
+        ));
+    }
+}
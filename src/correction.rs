@@ -0,0 +1,74 @@
+/*!
+Perceptual brightness correction. LED brightness values in
+this crate are linear duty cycles, but the eye's response
+to light is far from linear: a linear ramp seems to jump
+quickly through the dim end and crawl through the bright
+end. A [Correction] maps a perceptually linear value onto
+the duty cycle that produces it, so that linear animation
+ramps look smooth.
+*/
+
+use libm::{powf, roundf};
+
+/// Brightness correction curve.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Correction {
+    /// No correction: values are used as duty cycles
+    /// (default).
+    #[default]
+    Linear,
+    /// Power-law gamma correction with the given exponent,
+    /// typically 2.2 to 2.8. An exponent that is not
+    /// positive and finite is treated as linear.
+    Gamma(f32),
+    /// CIE 1931 lightness (L*) correction.
+    Cie,
+}
+
+impl Correction {
+    /// Gamma correction with the commonly used exponent
+    /// 2.2.
+    pub const GAMMA: Correction = Correction::Gamma(2.2);
+
+    /// Correct `value` on a scale of 0..=`max`, giving a
+    /// duty cycle on the same scale. Values above `max` are
+    /// treated as `max`.
+    pub fn apply(self, value: u8, max: u8) -> u8 {
+        if max == 0 {
+            return 0;
+        }
+        let x = value.min(max) as f32 / max as f32;
+        roundf(self.apply_f32(x) * max as f32) as u8
+    }
+
+    /// Correct `x` on a scale of 0.0..=1.0, giving a duty
+    /// cycle on the same scale. Values outside the scale
+    /// are clamped to it.
+    pub fn apply_f32(self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        match self {
+            Correction::Linear => x,
+            Correction::Gamma(gamma) if gamma.is_finite() && gamma > 0.0 => powf(x, gamma),
+            Correction::Gamma(_) => x,
+            Correction::Cie => {
+                let lightness = 100.0 * x;
+                if lightness <= 8.0 {
+                    lightness / 903.3
+                } else {
+                    let l = (lightness + 16.0) / 116.0;
+                    l * l * l
+                }
+            }
+        }
+    }
+
+    /// Correct each channel of `color`.
+    #[cfg(feature = "ambient")]
+    pub fn apply_rgb(self, color: crate::RGB8) -> crate::RGB8 {
+        crate::RGB8::new(
+            self.apply(color.r, 255),
+            self.apply(color.g, 255),
+            self.apply(color.b, 255),
+        )
+    }
+}
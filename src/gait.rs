@@ -0,0 +1,299 @@
+/*!
+Gait generator for a four-legged walker built on the
+Wukong's eight servo ports: each leg has a hip servo that
+swings the leg forward and back, and a knee servo that lifts
+it. The generator is non-blocking: call
+[Quadruped::update] regularly (every 20 ms or so) with the
+current time in milliseconds, and it will command every
+servo to its place in the gait cycle.
+
+Servo angles are always clamped to the range of the
+configured servo, so the gait stays within the
+[ServoConfig](crate::bus::ServoConfig) limits even with
+aggressive parameters.
+*/
+
+use crate::bus::{self, ConfiguredServo, Pose, ServoAngle, ServoLayout};
+
+use libm::sinf;
+use nrf52833_hal::twim;
+
+/// Error in gait setup.
+#[derive(Debug, Clone, Copy)]
+pub enum Error {
+    /// Gait cycle time must be positive.
+    InvalidCycleTime(u32),
+    /// Stride and step height must be finite and
+    /// non-negative.
+    InvalidDimension(f32),
+}
+
+/// Position of a leg on the walker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegPosition {
+    /// Front left leg.
+    FrontLeft,
+    /// Front right leg.
+    FrontRight,
+    /// Back left leg.
+    BackLeft,
+    /// Back right leg.
+    BackRight,
+}
+
+impl LegPosition {
+    /// All leg positions, in the order legs are given to
+    /// [Quadruped::new].
+    pub const ALL: [LegPosition; 4] = [
+        LegPosition::FrontLeft,
+        LegPosition::FrontRight,
+        LegPosition::BackLeft,
+        LegPosition::BackRight,
+    ];
+
+    fn is_left(self) -> bool {
+        matches!(self, LegPosition::FrontLeft | LegPosition::BackLeft)
+    }
+}
+
+/// Servo mapping for one leg, using servos in the
+/// [ServoLayout] `L`.
+#[derive(Debug, Clone, Copy)]
+pub struct Leg<L> {
+    hip: ConfiguredServo<L>,
+    hip_center: ServoAngle,
+    hip_reversed: bool,
+    knee: ConfiguredServo<L>,
+    knee_stand: ServoAngle,
+    knee_reversed: bool,
+}
+
+impl<L: ServoLayout> Leg<L> {
+    /// Make a new leg mapping. The `hip` servo points the
+    /// leg straight down at `hip_center`; the `knee` servo
+    /// puts the foot on the ground at `knee_stand`. A
+    /// `reversed` servo moves the leg backward (hip) or down
+    /// (knee) as its angle increases.
+    pub fn new(
+        hip: ConfiguredServo<L>,
+        hip_center: ServoAngle,
+        hip_reversed: bool,
+        knee: ConfiguredServo<L>,
+        knee_stand: ServoAngle,
+        knee_reversed: bool,
+    ) -> Self {
+        Self {
+            hip,
+            hip_center,
+            hip_reversed,
+            knee,
+            knee_stand,
+            knee_reversed,
+        }
+    }
+
+    /// Servo targets for a hip swing of `hip` degrees
+    /// (positive forward) and a knee lift of `lift` degrees,
+    /// clamped to the servos' ranges.
+    fn targets(&self, hip: f32, lift: f32) -> [(ConfiguredServo<L>, ServoAngle); 2] {
+        let hip = if self.hip_reversed { -hip } else { hip };
+        let lift = if self.knee_reversed { -lift } else { lift };
+        [
+            (self.hip, clamped(self.hip, self.hip_center, hip)),
+            (self.knee, clamped(self.knee, self.knee_stand, lift)),
+        ]
+    }
+}
+
+fn clamped<L>(servo: ConfiguredServo<L>, base: ServoAngle, offset: f32) -> ServoAngle {
+    let max = servo.max_angle().degrees_f32();
+    let degrees = (base.degrees_f32() + offset).clamp(0.0, max);
+    ServoAngle::from_degrees_f32(degrees).unwrap_or(servo.max_angle())
+}
+
+/// Direction of a [Gait::Turn].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Turn {
+    /// Turn counterclockwise seen from above.
+    Left,
+    /// Turn clockwise seen from above.
+    Right,
+}
+
+/// Gaits the walker can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gait {
+    /// Stand still with all feet down.
+    Stand,
+    /// Slow, stable walk lifting one leg at a time.
+    Walk,
+    /// Faster walk lifting diagonal leg pairs together.
+    Trot,
+    /// Turn in place, trotting with the two sides moving
+    /// in opposite directions.
+    Turn(Turn),
+}
+
+impl Gait {
+    /// Fraction of the cycle a leg spends in the air.
+    fn swing_fraction(self) -> f32 {
+        match self {
+            Gait::Walk => 0.25,
+            _ => 0.5,
+        }
+    }
+
+    /// Start of a leg's swing, as a fraction of the cycle.
+    fn phase_offset(self, leg: LegPosition) -> f32 {
+        use LegPosition::*;
+        match (self, leg) {
+            (Gait::Walk, FrontLeft) => 0.0,
+            (Gait::Walk, BackRight) => 0.25,
+            (Gait::Walk, FrontRight) => 0.5,
+            (Gait::Walk, BackLeft) => 0.75,
+            (_, FrontLeft | BackRight) => 0.0,
+            (_, FrontRight | BackLeft) => 0.5,
+        }
+    }
+
+    /// Direction of hip travel for a leg: positive walks
+    /// forward.
+    fn direction(self, leg: LegPosition) -> f32 {
+        match (self, leg.is_left()) {
+            (Gait::Turn(Turn::Left), true) | (Gait::Turn(Turn::Right), false) => -1.0,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Gait timing and dimensions.
+#[derive(Debug, Clone, Copy)]
+pub struct GaitParams {
+    cycle_time: u32,
+    stride: f32,
+    step_height: f32,
+}
+
+impl GaitParams {
+    /// Make new gait parameters. A full gait cycle takes
+    /// `cycle_time` milliseconds. Each hip swings `stride`
+    /// degrees either side of center, and each knee lifts
+    /// `step_height` degrees while its leg is in the air.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if `cycle_time` is zero.
+    /// * Returns an error if `stride` or `step_height` is
+    ///   negative or not finite.
+    pub fn new(cycle_time: u32, stride: f32, step_height: f32) -> Result<Self, Error> {
+        if cycle_time == 0 {
+            return Err(Error::InvalidCycleTime(cycle_time));
+        }
+        for dimension in [stride, step_height] {
+            if !dimension.is_finite() || dimension < 0.0 {
+                return Err(Error::InvalidDimension(dimension));
+            }
+        }
+        Ok(Self {
+            cycle_time,
+            stride,
+            step_height,
+        })
+    }
+}
+
+/// Four-legged walker gait engine, using servos in the
+/// [ServoLayout] `L`.
+#[derive(Debug, Clone)]
+pub struct Quadruped<L> {
+    legs: [Leg<L>; 4],
+    params: GaitParams,
+    gait: Gait,
+    start: u32,
+}
+
+impl<L: ServoLayout> Quadruped<L> {
+    /// Make a new walker from its `legs`, given in
+    /// [LegPosition::ALL] order, and gait `params`. The
+    /// walker starts out standing.
+    pub fn new(legs: [Leg<L>; 4], params: GaitParams) -> Self {
+        Self {
+            legs,
+            params,
+            gait: Gait::Stand,
+            start: 0,
+        }
+    }
+
+    /// Current gait.
+    pub fn gait(&self) -> Gait {
+        self.gait
+    }
+
+    /// Switch to the given `gait`, starting a new cycle at
+    /// time `now`. Switching to the current gait does not
+    /// restart the cycle.
+    pub fn set_gait(&mut self, gait: Gait, now: u32) {
+        if gait != self.gait {
+            self.gait = gait;
+            self.start = now;
+        }
+    }
+
+    /// Change the gait parameters. Takes effect on the next
+    /// update.
+    pub fn set_params(&mut self, params: GaitParams) {
+        self.params = params;
+    }
+
+    /// The servo pose for time `now`. The pose holds the
+    /// hip then knee servo of each leg, in
+    /// [LegPosition::ALL] order.
+    pub fn pose(&self, now: u32) -> Pose<L, 8> {
+        let GaitParams {
+            cycle_time,
+            stride,
+            step_height,
+        } = self.params;
+        let phase = (now.wrapping_sub(self.start) % cycle_time) as f32 / cycle_time as f32;
+        let swing = self.gait.swing_fraction();
+        let legs: [_; 4] = core::array::from_fn(|i| {
+            let position = LegPosition::ALL[i];
+            let (hip, lift) = if self.gait == Gait::Stand {
+                (0.0, 0.0)
+            } else {
+                let t = (phase - self.gait.phase_offset(position) + 1.0) % 1.0;
+                if t < swing {
+                    // In the air: swing forward from back to front.
+                    let s = t / swing;
+                    let hip = stride * (2.0 * s - 1.0);
+                    (hip, step_height * sinf(core::f32::consts::PI * s))
+                } else {
+                    // On the ground: push back from front to back.
+                    let s = (t - swing) / (1.0 - swing);
+                    (stride * (1.0 - 2.0 * s), 0.0)
+                }
+            };
+            self.legs[i].targets(hip * self.gait.direction(position), lift)
+        });
+        let targets = core::array::from_fn(|j| legs[j / 2][j % 2]);
+        Pose::clamped(targets)
+    }
+
+    /// Command every servo to its gait position for time
+    /// `now`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an I2C write fails.
+    pub fn update<TWIM>(
+        &mut self,
+        bus: &mut bus::WuKongBus<TWIM, L>,
+        now: u32,
+    ) -> Result<(), bus::Error>
+    where
+        TWIM: twim::Instance,
+    {
+        bus.set_servo_pose(&self.pose(now))
+    }
+}
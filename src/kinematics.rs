@@ -0,0 +1,409 @@
+/*!
+Inverse kinematics helpers for mechanisms built from Wukong
+servos. These turn a target point into a [Pose] of servo
+angles, checking every joint against its servo's limits so
+that unreachable targets are rejected before anything is
+sent on the bus.
+
+Two mechanisms are supported:
+
+* [TwoLinkArm]: a planar arm with a shoulder and an elbow
+  joint.
+
+* [PanTilt]: a pan-tilt mount, such as for a camera or
+  distance sensor.
+
+Joint angles are in radians, counterclockwise positive. A
+[Joint] describes how a servo is mounted: which servo angle
+corresponds to a joint angle of zero, and whether the servo
+turns the joint backward.
+
+Lengths and coordinates may be in any consistent unit.
+*/
+
+use crate::bus::{ConfiguredServo, Pose, Servo, ServoAngle, ServoLayout};
+
+use libm::{acosf, atan2f, cosf, sinf, sqrtf};
+
+/// Error during kinematics calculation.
+#[derive(Debug, Clone, Copy)]
+pub enum Error {
+    /// Target point is out of reach of the mechanism.
+    Unreachable,
+    /// Mechanism dimensions are not positive and finite.
+    BadGeometry,
+    /// Joint angle (radians) needed for the target is
+    /// outside the range of the given servo.
+    JointLimit(Servo, f32),
+}
+
+/// Mounting of a servo in the [ServoLayout] `L` driving a
+/// joint.
+#[derive(Debug, Clone, Copy)]
+pub struct Joint<L> {
+    servo: ConfiguredServo<L>,
+    zero: ServoAngle,
+    reversed: bool,
+}
+
+impl<L: ServoLayout> Joint<L> {
+    /// Make a new joint driven by `servo`. The servo angle
+    /// `zero` puts the joint at a joint angle of zero. If
+    /// `reversed` is true, increasing the servo angle
+    /// decreases the joint angle.
+    pub fn new(servo: ConfiguredServo<L>, zero: ServoAngle, reversed: bool) -> Self {
+        Self {
+            servo,
+            zero,
+            reversed,
+        }
+    }
+
+    /// The servo driving this joint.
+    pub fn servo(&self) -> ConfiguredServo<L> {
+        self.servo
+    }
+
+    /// Find the servo angle giving the joint angle `angle`
+    /// (radians).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the servo cannot reach the
+    /// needed angle.
+    pub fn servo_angle(&self, angle: f32) -> Result<ServoAngle, Error> {
+        let offset = if self.reversed { -angle } else { angle };
+        let degrees = self.zero.degrees_f32() + offset.to_degrees();
+        let servo_angle = ServoAngle::from_degrees_f32(degrees)
+            .map_err(|_| Error::JointLimit(self.servo.servo(), angle))?;
+        if servo_angle.millidegrees() > self.servo.max_angle().millidegrees() {
+            return Err(Error::JointLimit(self.servo.servo(), angle));
+        }
+        Ok(servo_angle)
+    }
+
+    /// The servo and its angle for the joint angle `angle`,
+    /// checked as by [Self::servo_angle].
+    fn target(&self, angle: f32) -> Result<(ConfiguredServo<L>, ServoAngle), Error> {
+        Ok((self.servo, self.servo_angle(angle)?))
+    }
+}
+
+/// Which way a [TwoLinkArm] bends its elbow when both
+/// solutions are possible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Elbow {
+    /// Elbow joint angle positive (counterclockwise).
+    Up,
+    /// Elbow joint angle negative (clockwise).
+    Down,
+}
+
+/// Planar arm with two links. The shoulder sits at the
+/// origin; a shoulder joint angle of zero points the upper
+/// link along the x axis, and an elbow joint angle of zero
+/// extends the lower link straight out from the upper.
+#[derive(Debug, Clone, Copy)]
+pub struct TwoLinkArm<L> {
+    upper: f32,
+    lower: f32,
+    shoulder: Joint<L>,
+    elbow: Joint<L>,
+    bend: Elbow,
+}
+
+impl<L: ServoLayout> TwoLinkArm<L> {
+    /// Make a new arm with the given `upper` and `lower`
+    /// link lengths, driven by the given `shoulder` and
+    /// `elbow` joints, preferring the given elbow `bend`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a link length is not positive and
+    /// finite.
+    pub fn new(
+        upper: f32,
+        lower: f32,
+        shoulder: Joint<L>,
+        elbow: Joint<L>,
+        bend: Elbow,
+    ) -> Result<Self, Error> {
+        let valid = |length: f32| length.is_finite() && length > 0.0;
+        if !valid(upper) || !valid(lower) {
+            return Err(Error::BadGeometry);
+        }
+        Ok(Self {
+            upper,
+            lower,
+            shoulder,
+            elbow,
+            bend,
+        })
+    }
+
+    /// Find the shoulder and elbow joint angles (radians)
+    /// that put the end of the arm at (`x`, `y`).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the point is out of reach.
+    pub fn joint_angles(&self, x: f32, y: f32) -> Result<(f32, f32), Error> {
+        let (l1, l2) = (self.upper, self.lower);
+        let d2 = x * x + y * y;
+        let c = (d2 - l1 * l1 - l2 * l2) / (2.0 * l1 * l2);
+        if !(-1.0..=1.0).contains(&c) {
+            return Err(Error::Unreachable);
+        }
+        let elbow = match self.bend {
+            Elbow::Up => acosf(c),
+            Elbow::Down => -acosf(c),
+        };
+        let (sin_elbow, cos_elbow) = (sinf(elbow), cosf(elbow));
+        let shoulder = atan2f(y, x) - atan2f(l2 * sin_elbow, l1 + l2 * cos_elbow);
+        Ok((shoulder, elbow))
+    }
+
+    /// Find the servo pose that puts the end of the arm at
+    /// (`x`, `y`). The pose holds the shoulder servo then
+    /// the elbow servo.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if the point is out of reach.
+    /// * Returns an error if a servo cannot reach the needed
+    ///   joint angle.
+    pub fn pose(&self, x: f32, y: f32) -> Result<Pose<L, 2>, Error> {
+        let (shoulder, elbow) = self.joint_angles(x, y)?;
+        Ok(Pose::from_checked([
+            self.shoulder.target(shoulder)?,
+            self.elbow.target(elbow)?,
+        ]))
+    }
+}
+
+/// Pan-tilt mount. The mount pivots at the origin, with the
+/// x axis forward, the y axis left and the z axis up. A pan
+/// joint angle of zero faces along the x axis, positive
+/// toward the y axis; a tilt joint angle of zero is level,
+/// positive upward.
+#[derive(Debug, Clone, Copy)]
+pub struct PanTilt<L> {
+    pan: Joint<L>,
+    tilt: Joint<L>,
+}
+
+impl<L: ServoLayout> PanTilt<L> {
+    /// Make a new pan-tilt mount driven by the given `pan`
+    /// and `tilt` joints.
+    pub fn new(pan: Joint<L>, tilt: Joint<L>) -> Self {
+        Self { pan, tilt }
+    }
+
+    /// Find the pan and tilt joint angles (radians) that aim
+    /// the mount at (`x`, `y`, `z`).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the point is at the origin or is
+    /// not finite.
+    pub fn joint_angles(&self, x: f32, y: f32, z: f32) -> Result<(f32, f32), Error> {
+        let horizontal = sqrtf(x * x + y * y);
+        if !(horizontal + z.abs()).is_finite() || (horizontal == 0.0 && z == 0.0) {
+            return Err(Error::Unreachable);
+        }
+        Ok((atan2f(y, x), atan2f(z, horizontal)))
+    }
+
+    /// Find the servo pose that aims the mount at (`x`, `y`,
+    /// `z`). The pose holds the pan servo then the tilt
+    /// servo.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if the point is at the origin or is
+    ///   not finite.
+    /// * Returns an error if a servo cannot reach the needed
+    ///   joint angle.
+    pub fn pose(&self, x: f32, y: f32, z: f32) -> Result<Pose<L, 2>, Error> {
+        let (pan, tilt) = self.joint_angles(x, y, z)?;
+        Ok(Pose::from_checked([
+            self.pan.target(pan)?,
+            self.tilt.target(tilt)?,
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::ServoConfig;
+    use core::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    #[derive(Debug, Clone, Copy)]
+    struct Rig;
+
+    impl ServoLayout for Rig {
+        const CONFIG: ServoConfig = crate::servo_config![1 => 180, 2 => 180];
+    }
+
+    const S1: ConfiguredServo<Rig> = crate::configured_servo!(Rig, 1);
+    const S2: ConfiguredServo<Rig> = crate::configured_servo!(Rig, 2);
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    /// Joints centered at 90°, so each can turn ±90°.
+    fn arm(upper: f32, lower: f32, bend: Elbow) -> TwoLinkArm<Rig> {
+        let center = crate::servo_angle!(90);
+        TwoLinkArm::new(
+            upper,
+            lower,
+            Joint::new(S1, center, false),
+            Joint::new(S2, center, false),
+            bend,
+        )
+        .unwrap()
+    }
+
+    /// Forward kinematics: the end point for the given joint
+    /// angles.
+    fn forward(arm: &TwoLinkArm<Rig>, shoulder: f32, elbow: f32) -> (f32, f32) {
+        let x = arm.upper * cosf(shoulder) + arm.lower * cosf(shoulder + elbow);
+        let y = arm.upper * sinf(shoulder) + arm.lower * sinf(shoulder + elbow);
+        (x, y)
+    }
+
+    #[test]
+    fn two_link_known_geometry() {
+        let (shoulder, elbow) = arm(1.0, 1.0, Elbow::Up).joint_angles(1.0, 1.0).unwrap();
+        assert!(close(shoulder, 0.0) && close(elbow, FRAC_PI_2));
+        let (shoulder, elbow) = arm(1.0, 1.0, Elbow::Down).joint_angles(1.0, 1.0).unwrap();
+        assert!(close(shoulder, FRAC_PI_2) && close(elbow, -FRAC_PI_2));
+        // Fully extended along the diagonal.
+        let d = core::f32::consts::SQRT_2;
+        let (shoulder, elbow) = arm(1.0, 1.0, Elbow::Up).joint_angles(d, d).unwrap();
+        assert!(close(shoulder, FRAC_PI_4) && close(elbow, 0.0));
+    }
+
+    #[test]
+    fn two_link_round_trip() {
+        for bend in [Elbow::Up, Elbow::Down] {
+            let arm = arm(3.0, 2.0, bend);
+            for (x, y) in [(4.0, 1.0), (1.5, 2.5), (-2.0, 3.0), (1.0, -1.0), (4.9, 0.0)] {
+                let (shoulder, elbow) = arm.joint_angles(x, y).unwrap();
+                let (fx, fy) = forward(&arm, shoulder, elbow);
+                assert!(close(fx, x) && close(fy, y), "({x}, {y}) -> ({fx}, {fy})");
+            }
+        }
+    }
+
+    #[test]
+    fn two_link_pose() {
+        let pose = arm(1.0, 1.0, Elbow::Up).pose(1.0, 1.0).unwrap();
+        let [(shoulder, a), (elbow, b)] = *pose.targets();
+        assert_eq!(u8::from(shoulder.servo()), 0);
+        assert_eq!(u8::from(elbow.servo()), 1);
+        assert_eq!(a.millidegrees(), 90_000);
+        assert_eq!(b.millidegrees(), 180_000);
+    }
+
+    #[test]
+    fn unreachable_targets() {
+        let arm = arm(1.0, 1.0, Elbow::Up);
+        // Too far, and not a number.
+        for (x, y) in [(2.5, 0.0), (f32::NAN, 0.0)] {
+            assert!(matches!(arm.joint_angles(x, y), Err(Error::Unreachable)));
+            assert!(matches!(arm.pose(x, y), Err(Error::Unreachable)));
+        }
+        // Too near for unequal links.
+        let arm = TwoLinkArm::new(3.0, 1.0, arm.shoulder, arm.elbow, Elbow::Up).unwrap();
+        assert!(matches!(arm.pose(1.0, 0.0), Err(Error::Unreachable)));
+
+        let center = crate::servo_angle!(90);
+        let joint = Joint::new(S1, center, false);
+        let mount = PanTilt::new(joint, Joint::new(S2, center, false));
+        assert!(matches!(mount.pose(0.0, 0.0, 0.0), Err(Error::Unreachable)));
+        assert!(matches!(
+            mount.pose(f32::INFINITY, 0.0, 0.0),
+            Err(Error::Unreachable)
+        ));
+    }
+
+    #[test]
+    fn bad_geometry() {
+        let joint = Joint::new(S1, crate::servo_angle!(0), false);
+        for (upper, lower) in [(0.0, 1.0), (1.0, -1.0), (f32::NAN, 1.0)] {
+            assert!(matches!(
+                TwoLinkArm::new(upper, lower, joint, joint, Elbow::Up),
+                Err(Error::BadGeometry)
+            ));
+        }
+    }
+
+    #[test]
+    fn joint_limits() {
+        let joint = Joint::new(S1, crate::servo_angle!(90), false);
+        assert_eq!(
+            joint.servo_angle(FRAC_PI_2).unwrap().millidegrees(),
+            180_000
+        );
+        assert_eq!(joint.servo_angle(-FRAC_PI_2).unwrap().millidegrees(), 0);
+        assert!(matches!(
+            joint.servo_angle(FRAC_PI_2 + 0.1),
+            Err(Error::JointLimit(_, _))
+        ));
+        assert!(matches!(
+            joint.servo_angle(-FRAC_PI_2 - 0.1),
+            Err(Error::JointLimit(_, _))
+        ));
+
+        let reversed = Joint::new(S1, crate::servo_angle!(0), true);
+        assert_eq!(
+            reversed.servo_angle(-FRAC_PI_4).unwrap().millidegrees(),
+            45_000
+        );
+        assert!(matches!(
+            reversed.servo_angle(FRAC_PI_4),
+            Err(Error::JointLimit(_, _))
+        ));
+
+        // Reachable, but the elbow would have to bend past its
+        // servo's range.
+        let center = crate::servo_angle!(90);
+        let arm = TwoLinkArm::new(
+            1.0,
+            1.0,
+            Joint::new(S1, center, false),
+            Joint::new(S2, crate::servo_angle!(0), false),
+            Elbow::Down,
+        )
+        .unwrap();
+        match arm.pose(1.0, 1.0) {
+            Err(Error::JointLimit(servo, angle)) => {
+                assert_eq!(u8::from(servo), 1);
+                assert!(close(angle, -FRAC_PI_2));
+            }
+            other => panic!("expected joint limit, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn pan_tilt_aim() {
+        let center = crate::servo_angle!(90);
+        let mount = PanTilt::new(Joint::new(S1, center, false), Joint::new(S2, center, false));
+        let (pan, tilt) = mount.joint_angles(0.0, 1.0, 0.0).unwrap();
+        assert!(close(pan, FRAC_PI_2) && close(tilt, 0.0));
+        let (pan, tilt) = mount.joint_angles(1.0, 0.0, 1.0).unwrap();
+        assert!(close(pan, 0.0) && close(tilt, FRAC_PI_4));
+        let pose = mount.pose(1.0, 1.0, 0.0).unwrap();
+        let [(_, pan), (_, tilt)] = *pose.targets();
+        assert_eq!(pan.millidegrees(), 135_000);
+        assert_eq!(tilt.millidegrees(), 90_000);
+        // Aiming behind needs more pan than the servo has.
+        assert!(matches!(
+            mount.pose(-1.0, -0.1, 0.0),
+            Err(Error::JointLimit(_, _))
+        ));
+    }
+}
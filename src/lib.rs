@@ -43,7 +43,15 @@ documentation.
 
 * *Servo* (`servo`): The Wukong has eight servo controllers
   with pins on the board. This crate can set the angle of
-  these servos.
+  these servos, and can record servo motions for later
+  replay.
+
+The optional `kinematics` feature (off by default) adds
+inverse kinematics helpers for servo arms and pan-tilt
+mounts. The optional `gait` feature (off by default) adds a
+gait generator for four-legged walkers. The optional `status`
+feature (off by default) adds priority-based status
+indication through the ambient LEDs and mood lights.
 */
 
 #![no_std]
@@ -54,17 +62,31 @@ pub mod ambient;
 pub mod bus;
 #[cfg(feature = "buzzer")]
 pub mod buzzer;
+#[cfg(any(feature = "ambient", feature = "mood_lights"))]
+pub mod correction;
+#[cfg(feature = "gait")]
+pub mod gait;
+#[cfg(feature = "kinematics")]
+pub mod kinematics;
+#[cfg(feature = "status")]
+pub mod status;
 
 #[cfg(feature = "ambient")]
-pub use ambient::{WuKongAmbient, RGB8};
+pub use ambient::{Corner, WuKongAmbient, RGB8};
 #[cfg(feature = "buzzer")]
 pub use buzzer::WuKongBuzzer;
+#[cfg(any(feature = "ambient", feature = "mood_lights"))]
+pub use correction::Correction;
 
 #[cfg(feature = "bus")]
 pub use bus::WuKongBus;
 #[cfg(feature = "mood_lights")]
-pub use bus::MoodLights;
+pub use bus::{MoodLightController, MoodLights};
 #[cfg(feature = "motor")]
 pub use bus::Motor;
 #[cfg(feature = "servo")]
-pub use bus::{Servo, ServoAngle, ServoConfig};
+pub use bus::{
+    ConfiguredServo, Pose, Servo, ServoAngle, ServoConfig, ServoGroup, ServoLayout, ServoLink,
+};
+#[cfg(feature = "status")]
+pub use status::{Indication, StatusIndicator};
@@ -0,0 +1,361 @@
+/*!
+Priority-based status indication through the ambient LEDs
+and the mood lights. Several parts of a program can signal
+at once — low battery, error, radio link, idle — and a
+[StatusIndicator] decides what the lights show.
+
+Each source of status has its own slot, identified by an
+index chosen by the program. A source posts an [Indication]
+to its slot, replacing whatever it posted before, and can
+clear it again; an indication may also have a timeout after
+which it clears itself.
+
+The ambient LEDs and the mood lights are composited
+separately. Each shows the highest-priority active
+indication that has something for it to show, with ties
+going to the most recently posted. So a high-priority alert
+preempts lower-priority indications, and when it finishes
+or is cleared they are restored; an indication that only
+uses the mood lights leaves the ambient LEDs to lower
+priorities. Animations keep their timing from when they
+were posted, so a restored animation picks up where it
+would have been. With no indication for them, the lights
+are turned off.
+
+[StatusIndicator::update] is non-blocking, and should be
+called regularly with the current time in milliseconds.
+*/
+
+use crate::ambient::{
+    self,
+    animation::{self, Animation, Animator},
+    ws2812, WuKongAmbient, RGB8,
+};
+use crate::bus::{self, mood_lights, MoodLightController, MoodLights};
+
+use nrf52833_hal::twim;
+
+/// Error in posting a status indication.
+#[derive(Debug, Clone, Copy)]
+pub enum Error {
+    /// No such source slot.
+    InvalidSource(usize),
+    /// Bad ambient animation.
+    Animation(animation::Error),
+    /// Bad mood light mode.
+    MoodLight(mood_lights::Error),
+}
+
+impl From<animation::Error> for Error {
+    fn from(err: animation::Error) -> Self {
+        Error::Animation(err)
+    }
+}
+
+impl From<mood_lights::Error> for Error {
+    fn from(err: mood_lights::Error) -> Self {
+        Error::MoodLight(err)
+    }
+}
+
+/// Error in updating the lights.
+pub enum UpdateError<P> {
+    /// Ambient LED error.
+    Ambient(ambient::Error<P>),
+    /// Bus error while setting the mood lights.
+    Bus(bus::Error),
+}
+
+impl<P> core::fmt::Debug for UpdateError<P> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            UpdateError::Ambient(err) => write!(f, "ambient error: {:?}", err),
+            UpdateError::Bus(err) => write!(f, "bus error: {:?}", err),
+        }
+    }
+}
+
+impl<P> From<ambient::Error<P>> for UpdateError<P> {
+    fn from(err: ambient::Error<P>) -> Self {
+        UpdateError::Ambient(err)
+    }
+}
+
+impl<P> From<bus::Error> for UpdateError<P> {
+    fn from(err: bus::Error) -> Self {
+        UpdateError::Bus(err)
+    }
+}
+
+/// What an indication shows on the ambient LEDs.
+#[derive(Debug, Clone, Copy)]
+pub enum AmbientPattern {
+    /// All LEDs the same color.
+    Solid(RGB8),
+    /// Each chassis corner its own color, in
+    /// [Corner::ALL](crate::Corner::ALL) order.
+    Corners([RGB8; 4]),
+    /// A running animation.
+    Animated(Animation),
+}
+
+/// A status indication. Build one with
+/// [Indication::new] and the `with_` methods.
+#[derive(Debug, Clone, Copy)]
+pub struct Indication {
+    priority: u8,
+    ambient: Option<AmbientPattern>,
+    mood_lights: Option<MoodLights>,
+    timeout: Option<u32>,
+}
+
+impl Indication {
+    /// A new indication with the given `priority`: higher
+    /// priorities preempt lower ones. It shows nothing and
+    /// has no timeout.
+    pub const fn new(priority: u8) -> Self {
+        Self {
+            priority,
+            ambient: None,
+            mood_lights: None,
+            timeout: None,
+        }
+    }
+
+    /// This indication showing `pattern` on the ambient
+    /// LEDs.
+    pub const fn with_ambient(self, pattern: AmbientPattern) -> Self {
+        Self {
+            ambient: Some(pattern),
+            ..self
+        }
+    }
+
+    /// This indication setting the mood lights to `mode`.
+    pub const fn with_mood_lights(self, mode: MoodLights) -> Self {
+        Self {
+            mood_lights: Some(mode),
+            ..self
+        }
+    }
+
+    /// This indication clearing itself `timeout`
+    /// milliseconds after it is posted.
+    pub const fn with_timeout(self, timeout: u32) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// The priority.
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// The ambient LED pattern, if any.
+    pub fn ambient(&self) -> Option<&AmbientPattern> {
+        self.ambient.as_ref()
+    }
+
+    /// The mood light mode, if any.
+    pub fn mood_lights(&self) -> Option<MoodLights> {
+        self.mood_lights
+    }
+
+    /// The timeout in milliseconds, if any.
+    pub fn timeout(&self) -> Option<u32> {
+        self.timeout
+    }
+}
+
+/// A posted indication.
+#[derive(Debug, Clone, Copy)]
+struct Post {
+    indication: Indication,
+    /// Time posted.
+    time: u32,
+    /// Post sequence number, to tell posts apart and order
+    /// them.
+    serial: u32,
+}
+
+/// What a light is currently showing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shown {
+    /// Not yet set.
+    Unknown,
+    /// Off, with no indication for it.
+    Idle,
+    /// The post with the given serial number.
+    Post(u32),
+}
+
+/// Compositor for status indications from `S` sources.
+#[derive(Debug, Clone)]
+pub struct StatusIndicator<const S: usize> {
+    posts: [Option<Post>; S],
+    serial: u32,
+    ambient_shown: Shown,
+    animator: Option<Animator>,
+    mood_shown: Shown,
+}
+
+impl<const S: usize> Default for StatusIndicator<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const S: usize> StatusIndicator<S> {
+    /// A new status indicator with no indications posted.
+    pub const fn new() -> Self {
+        Self {
+            posts: [None; S],
+            serial: 0,
+            ambient_shown: Shown::Unknown,
+            animator: None,
+            mood_shown: Shown::Unknown,
+        }
+    }
+
+    /// Post `indication` for `source` (0..`S`) at time
+    /// `now`, replacing any indication it already has. The
+    /// lights change on the next update.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if `source` is out of range.
+    /// * Returns an error if the ambient animation period is
+    ///   zero.
+    /// * Returns an error if the mood light intensity or
+    ///   breath depth is out of range.
+    pub fn post(&mut self, source: usize, indication: Indication, now: u32) -> Result<(), Error> {
+        let slot = self
+            .posts
+            .get_mut(source)
+            .ok_or(Error::InvalidSource(source))?;
+        if let Some(AmbientPattern::Animated(animation)) = indication.ambient {
+            Animator::new(animation, now)?;
+        }
+        if let Some(mode) = indication.mood_lights {
+            mode.frames()?;
+        }
+        self.serial = self.serial.wrapping_add(1);
+        *slot = Some(Post {
+            indication,
+            time: now,
+            serial: self.serial,
+        });
+        Ok(())
+    }
+
+    /// Clear any indication for `source` (0..`S`). The lights
+    /// change on the next update.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `source` is out of range.
+    pub fn clear(&mut self, source: usize) -> Result<(), Error> {
+        let slot = self
+            .posts
+            .get_mut(source)
+            .ok_or(Error::InvalidSource(source))?;
+        *slot = None;
+        Ok(())
+    }
+
+    /// The indication posted for `source`, if any. An
+    /// indication that has timed out is reported until the
+    /// next update.
+    pub fn indication(&self, source: usize) -> Option<&Indication> {
+        self.posts
+            .get(source)?
+            .as_ref()
+            .map(|post| &post.indication)
+    }
+
+    /// The winning post for a light, among posts accepted by
+    /// `shows`.
+    fn winner(&self, shows: impl Fn(&Indication) -> bool) -> Option<&Post> {
+        self.posts
+            .iter()
+            .flatten()
+            .filter(|post| shows(&post.indication))
+            .max_by(|a, b| {
+                let age = |post: &Post| self.serial.wrapping_sub(post.serial);
+                a.indication
+                    .priority
+                    .cmp(&b.indication.priority)
+                    .then(age(b).cmp(&age(a)))
+            })
+    }
+
+    /// Clear timed-out indications and show the winning
+    /// indications at time `now`: update the ambient LEDs
+    /// and send mood light changes as the protocol allows.
+    /// Call this regularly; apart from LED transfers and
+    /// I2C writes it does not block.
+    ///
+    /// # Errors
+    ///
+    /// * Returns an error if an LED transfer fails.
+    /// * Returns an error if an I2C write fails.
+    pub fn update<P, TWIM, L>(
+        &mut self,
+        ambient: &mut WuKongAmbient<P>,
+        controller: &mut MoodLightController,
+        bus: &mut bus::WuKongBus<TWIM, L>,
+        now: u32,
+    ) -> Result<(), UpdateError<P>>
+    where
+        P: ws2812::Instance,
+        TWIM: twim::Instance,
+    {
+        for slot in &mut self.posts {
+            if let Some(post) = slot {
+                if let Some(timeout) = post.indication.timeout {
+                    if now.wrapping_sub(post.time) >= timeout {
+                        *slot = None;
+                    }
+                }
+            }
+        }
+
+        let winner = self
+            .winner(|indication| indication.ambient.is_some())
+            .copied();
+        let shown = winner.map_or(Shown::Idle, |post| Shown::Post(post.serial));
+        if shown != self.ambient_shown {
+            self.animator = None;
+            match winner.and_then(|post| Some((post.indication.ambient?, post.time))) {
+                None => ambient.set_all(RGB8::default())?,
+                Some((AmbientPattern::Solid(color), _)) => ambient.set_all(color)?,
+                Some((AmbientPattern::Corners(colors), _)) => ambient.set_corner_colors(colors)?,
+                Some((AmbientPattern::Animated(animation), time)) => {
+                    // Checked when posted.
+                    self.animator = Animator::new(animation, time).ok();
+                }
+            }
+            self.ambient_shown = shown;
+        }
+        if let Some(animator) = &mut self.animator {
+            animator.update(ambient, now)?;
+        }
+
+        let winner = self
+            .winner(|indication| indication.mood_lights.is_some())
+            .copied();
+        let shown = winner.map_or(Shown::Idle, |post| Shown::Post(post.serial));
+        if shown != self.mood_shown {
+            self.mood_shown = shown;
+            let mode = winner
+                .and_then(|post| post.indication.mood_lights)
+                .unwrap_or(MoodLights::Off);
+            controller.set(bus, mode, now)?;
+        } else {
+            controller.poll(bus, now)?;
+        }
+        Ok(())
+    }
+}